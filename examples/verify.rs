@@ -0,0 +1,95 @@
+/*
+* Check IntervalSetMapping and IntervalListMapping files for corruption
+*
+* Usage: cargo run --example verify -- [--kind set|list] [--payload-len N] [--max-problems N]
+*        FILE...
+*
+* Exits with a non-zero status if any file has errors. This is an example rather than a binary
+* so that it is not built (and linked against Python) with the extension-module feature.
+*/
+
+extern crate rs_intervalset;
+
+use std::env;
+use std::process;
+
+use rs_intervalset::Kind;
+use rs_intervalset::verify::verify_file;
+
+const USAGE: &str =
+    "usage: verify [--kind set|list] [--payload-len N] [--max-problems N] FILE...";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, USAGE);
+    process::exit(2);
+}
+
+fn parse_number(flag: &str, value: Option<String>) -> usize {
+    match value.map(|v| v.parse()) {
+        Some(Ok(v)) => v,
+        _ => usage_error(&format!("{} expects a number", flag))
+    }
+}
+
+fn main() {
+    let mut kind = None;
+    let mut payload_len = None;
+    let mut max_problems = 1000;
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kind" => kind = match args.next().as_deref() {
+                Some("set") => Some(Kind::Set),
+                Some("list") => Some(Kind::List),
+                _ => usage_error("--kind expects set or list")
+            },
+            "--payload-len" => payload_len = Some(parse_number(&arg, args.next())),
+            "--max-problems" => max_problems = parse_number(&arg, args.next()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => paths.push(arg)
+        }
+    }
+    if paths.is_empty() {
+        usage_error("no files given");
+    }
+
+    let mut ok = true;
+    for path in &paths {
+        match verify_file(path, kind, payload_len, max_problems) {
+            Ok(report) => {
+                println!("{}: {} ids, {} intervals, header: {}, index: {}",
+                         path, report.id_count, report.interval_count,
+                         report.has_header, report.has_index);
+                for problem in &report.problems {
+                    let mut location = String::new();
+                    if let Some(id) = problem.id {
+                        location.push_str(&format!(" id {}", id));
+                    }
+                    if let Some(index) = problem.index {
+                        location.push_str(&format!(" interval {}", index));
+                    }
+                    println!("  offset {}:{} {} [{}]: {}",
+                             problem.offset, location,
+                             if problem.kind.is_error() { "error" } else { "warning" },
+                             problem.kind.name(), problem.message);
+                }
+                if report.problems_truncated {
+                    println!("  more than {} problems, stopped reporting", max_problems);
+                }
+                ok &= report.is_ok();
+            },
+            Err(e) => {
+                println!("{}: {}", path, e);
+                ok = false;
+            }
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
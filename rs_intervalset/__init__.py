@@ -1,3 +1,10 @@
-from .rs_intervalset import MmapIntervalSetMapping, MmapIntervalListMapping
+from .rs_intervalset import MmapIntervalSetMapping, MmapIntervalListMapping, \
+    CombinedMapping, ListUnionMapping, SetUnionMapping, \
+    SetIntersectionMapping, PayloadPredicate, Expr, Query, \
+    IntervalSetMappingWriter, IntervalListMappingWriter, verify
 
-__all__ = ['MmapIntervalSetMapping', 'MmapIntervalListMapping']
+__all__ = ['MmapIntervalSetMapping', 'MmapIntervalListMapping',
+           'CombinedMapping', 'ListUnionMapping', 'SetUnionMapping',
+           'SetIntersectionMapping', 'PayloadPredicate', 'Expr', 'Query',
+           'IntervalSetMappingWriter',
+           'IntervalListMappingWriter', 'verify']
@@ -1,8 +1,8 @@
-import heapq
 from abc import ABC, abstractmethod
 from typing import List, Tuple, Iterable, Set, Optional
 
-from .rs_intervalset import MmapIntervalListMapping, MmapIntervalSetMapping
+from .rs_intervalset import MmapIntervalListMapping, MmapIntervalSetMapping, \
+    CombinedMapping, ListUnionMapping, SetIntersectionMapping, SetUnionMapping
 
 Interval = Tuple[int, int]
 
@@ -64,6 +64,45 @@ class AbstractMmapISetWrapper(ABC):
                    for a, b in self.get_intervals(i, True))
 
 
+class _CombinedMappingWrapper(AbstractMmapISetWrapper):
+    """Delegates to a mapping combined in Rust"""
+
+    def __init__(self, combined: CombinedMapping):
+        self._combined = combined
+
+    def len(self) -> int:
+        return self._combined.len()
+
+    def get_ids(self) -> List[int]:
+        return self._combined.get_ids()
+
+    def has_id(self, i: int) -> bool:
+        return self._combined.has_id(i)
+
+    def get_intervals(self, i: int, use_default: bool) -> List[Interval]:
+        return self._combined.get_intervals(i, use_default)
+
+    def is_contained(self, i: int, target: int, use_default: bool) -> bool:
+        return self._combined.is_contained(i, target, use_default)
+
+    def intersect(self, i: int, intervals: List[Interval],
+                  use_default: bool) -> List[Interval]:
+        return self._combined.intersect(i, intervals, use_default)
+
+    def has_intersection(
+        self, i: int, start: int, end: int, use_default: bool
+    ) -> bool:
+        return self._combined.has_intersection(i, start, end, use_default)
+
+    def intersect_sum(
+        self, i: int, intervals: List[Interval], use_default: bool
+    ) -> int:
+        return self._combined.intersect_sum(i, intervals, use_default)
+
+    def sum(self) -> int:
+        return self._combined.sum()
+
+
 class MmapIListToISetMapping(AbstractMmapISetWrapper):
 
     def __init__(self, ilistmap: MmapIntervalListMapping,
@@ -72,7 +111,7 @@ class MmapIListToISetMapping(AbstractMmapISetWrapper):
         self._ilistmap = ilistmap
         self._payload_mask = payload_mask
         self._payload_value = payload_value
-        self._search_window = search_window
+        # search_window is no longer needed: containment is checked exactly
         self._fuzz = fuzz
 
     def len(self) -> int:
@@ -85,24 +124,20 @@ class MmapIListToISetMapping(AbstractMmapISetWrapper):
         return self._ilistmap.has_id(i)
 
     def get_intervals(self, i: int, use_default: bool) -> List[Interval]:
-        return _deoverlap(
-            self._ilistmap.get_intervals(
-                i, self._payload_mask, self._payload_value, use_default),
-            self._fuzz)
+        return self._ilistmap.coalesce(
+            i, self._fuzz, self._payload_mask, self._payload_value,
+            use_default)
 
     def sum(self) -> int:
         total = 0
         for i in self._ilistmap.get_ids():
-            for a, b in _deoverlap(
-                self._ilistmap.get_intervals(i, 0, 0, False), self._fuzz
-            ):
+            for a, b in self._ilistmap.coalesce(i, self._fuzz, 0, 0, False):
                 total += max(0, b - a)
         return total
 
     def is_contained(self, i: int, target: int, use_default: bool) -> bool:
         return self._ilistmap.is_contained(
-            i, target, self._payload_mask, self._payload_value, use_default,
-            self._search_window)
+            i, target, self._payload_mask, self._payload_value, use_default)
 
     def intersect(self, i: int, intervals: List[Interval],
                   use_default: bool) -> List[Interval]:
@@ -120,65 +155,14 @@ class MmapIListToISetMapping(AbstractMmapISetWrapper):
             use_default)
 
 
-class MmapUnionIlistsToISetMapping(AbstractMmapISetWrapper):
+class MmapUnionIlistsToISetMapping(_CombinedMappingWrapper):
 
     def __init__(self, ilistmaps: List[MmapIntervalListMapping],
                  payload_mask: int, payload_value: int, search_window: int,
                  fuzz: int = 0):
-        self._ilistmaps = ilistmaps
-        self._payload_mask = payload_mask
-        self._payload_value = payload_value
-        self._search_window = search_window
-        self._fuzz = fuzz
-
-        self.__ids: Optional[List[int]] = None
-
-    @property
-    def _ids(self) -> List[int]:
-        if self.__ids is None:
-            ids = set()
-            for ilistmap in self._ilistmaps:
-                ids.update(ilistmap.get_ids())
-            self.__ids = list(sorted(ids))
-        return self.__ids
-
-    def len(self) -> int:
-        return len(self._ids)
-
-    def get_ids(self) -> List[int]:
-        return self._ids
-
-    def has_id(self, i: int) -> bool:
-        return any(ilistmap.has_id(i) for ilistmap in self._ilistmaps)
-
-    def get_intervals(self, i: int, use_default: bool) -> List[Interval]:
-        results = []
-        for ilistmap in self._ilistmaps:
-            intervals = ilistmap.get_intervals(
-                i, self._payload_mask, self._payload_value, use_default)
-            if intervals:
-                results.append(intervals)
-        return _deoverlap(heapq.merge(*results), self._fuzz)
-
-    def is_contained(self, i: int, target: int, use_default: bool) -> bool:
-        for ilistmap in self._ilistmaps:
-            if ilistmap.is_contained(
-                i, target, self._payload_mask, self._payload_value,
-                use_default, self._search_window
-            ):
-                return True
-        return False
-
-    def intersect(self, i: int, intervals: List[Interval],
-                  use_default: bool) -> List[Interval]:
-        results = []
-        for ilistmap in self._ilistmaps:
-            if ilistmap.has_id(i):
-                results.append(
-                    ilistmap.intersect(
-                        i, intervals, self._payload_mask, self._payload_value,
-                        use_default))
-        return _deoverlap(heapq.merge(*results), self._fuzz)
+        # search_window is no longer needed: containment is checked exactly
+        super().__init__(ListUnionMapping(
+            ilistmaps, payload_mask, payload_value, fuzz))
 
 
 class MmapISetSubsetMapping(AbstractMmapISetWrapper):
@@ -240,124 +224,13 @@ class MmapISetSubsetMapping(AbstractMmapISetWrapper):
             raise IndexError('id not found')
 
 
-class MmapISetIntersectionMapping(AbstractMmapISetWrapper):
+class MmapISetIntersectionMapping(_CombinedMappingWrapper):
 
     def __init__(self, isetmaps: List[MmapIntervalSetMapping]):
-        self._isetmaps = isetmaps
-        self.__ids: Optional[List[int]] = None
-
-    @property
-    def _ids(self) -> List[int]:
-        if self.__ids is None:
-            ids = None
-            for isetmap in self._isetmaps:
-                if ids is None:
-                    ids = set(isetmap.get_ids())
-                else:
-                    ids.intersection_update(isetmap.get_ids())
-            self.__ids = list(sorted(ids))
-        return self.__ids
-
-    def len(self) -> int:
-        return len(self._ids)
-
-    def get_ids(self) -> List[int]:
-        return self._ids
-
-    def has_id(self, i: int) -> bool:
-        return all(isetmap.has_id(i) for isetmap in self._isetmaps)
-
-    def get_intervals(self, i: int, use_default: bool) -> List[Interval]:
-        if use_default or i in self._ids:
-            intervals = None
-            for isetmap in self._isetmaps:
-                if intervals is None:
-                    intervals = isetmap.get_intervals(i, use_default)
-                else:
-                    intervals = isetmap.intersect(i, intervals, use_default)
-            return intervals
-        else:
-            raise IndexError('id not found')
-
-    def is_contained(self, i: int, target: int, use_default: bool) -> bool:
-        return all(isetmap.is_contained(i, target, use_default)
-                   for isetmap in self._isetmaps)
-
-    def intersect(self, i: int, intervals: List[Interval],
-                  use_default: bool) -> List[Interval]:
-        if use_default or i in self._ids:
-            for isetmap in self._isetmaps:
-                intervals = isetmap.intersect(i, intervals, use_default)
-                if len(intervals) == 0:
-                    break
-            return intervals
-        else:
-            raise IndexError('id not found')
+        super().__init__(SetIntersectionMapping(isetmaps))
 
-    def intersect_sum(self, i: int, intervals: List[Interval],
-                      use_default: bool) -> int:
-        if use_default or i in self._ids:
-            result = 0
-            for j, isetmap in enumerate(self._isetmaps):
-                if j == len(self._isetmaps) - 1:
-                    result = isetmap.intersect_sum(i, intervals, use_default)
-                else:
-                    intervals = isetmap.intersect(i, intervals, use_default)
-                    if len(intervals) == 0:
-                        break
-            return result
-        else:
-            raise IndexError('id not found')
 
-
-class MmapISetUnionMapping(AbstractMmapISetWrapper):
+class MmapISetUnionMapping(_CombinedMappingWrapper):
 
     def __init__(self, isetmaps: List[MmapIntervalSetMapping]):
-        self._isetmaps = isetmaps
-        self.__ids: Optional[List[int]] = None
-
-    @property
-    def _ids(self) -> List[int]:
-        if self.__ids is None:
-            ids = None
-            for isetmap in self._isetmaps:
-                if ids is None:
-                    ids = set(isetmap.get_ids())
-                else:
-                    ids.update(isetmap.get_ids())
-            self.__ids = list(sorted(ids))
-        return self.__ids
-
-    def len(self) -> int:
-        return len(self._ids)
-
-    def get_ids(self) -> List[int]:
-        return self._ids
-
-    def has_id(self, i: int) -> bool:
-        return any(isetmap.has_id(i) for isetmap in self._isetmaps)
-
-    def get_intervals(self, i: int, use_default: bool) -> List[Interval]:
-        results = []
-        for isetmap in self._isetmaps:
-            intervals = isetmap.get_intervals(i, True)
-            if intervals:
-                results.append(intervals)
-        if not results and not use_default:
-            raise IndexError('id not found')
-        return _deoverlap(heapq.merge(*results), 0)
-
-    def is_contained(self, i: int, target: int, use_default: bool) -> bool:
-        return any(isetmap.is_contained(i, target, use_default)
-                   for isetmap in self._isetmaps)
-
-    def intersect(self, i: int, intervals: List[Interval],
-                  use_default: bool) -> List[Interval]:
-        results = []
-        for isetmap in self._isetmaps:
-            intervals = isetmap.intersect(i, intervals, use_default)
-            if intervals:
-                results.append(intervals)
-        if not results and not use_default:
-            raise IndexError('id not found')
-        return _deoverlap(heapq.merge(*results), 0)
+        super().__init__(SetUnionMapping(isetmaps))
@@ -1,12 +1,176 @@
+import os
 from typing import List, Tuple, Optional, BinaryIO
 
+HEADER_MAGIC = b'RSINTVAL'
+HEADER_SIZE = 16
+FORMAT_VERSION = 1
+KIND_SET = 0
+KIND_LIST = 1
+FLAG_SORTED = 0x1
+FLAG_NON_OVERLAPPING = 0x2
+
+INDEX_MAGIC = b'RSIDX001'
+INDEX_TRAILER_SIZE = 16
+
+IndexEntry = Tuple[int, int, int]
+
+
+def _fmt_u32(v: int) -> bytes:
+    return v.to_bytes(4, byteorder='little')
+
+
+def _fmt_u64(v: int) -> bytes:
+    return v.to_bytes(8, byteorder='little')
+
+
+def _fmt_header(kind: int, payload_len: int, flags: int) -> bytes:
+    return (HEADER_MAGIC + FORMAT_VERSION.to_bytes(2, byteorder='little')
+            + bytes([kind, payload_len, 4, flags, 0, 0]))
+
+
+def _check_header(fp: BinaryIO, kind: int, payload_len: int,
+                  header: bool) -> bool:
+    """
+    Check that appending to a file will not change its header, and return
+    whether it has one.
+    """
+    fp.seek(0)
+    data = fp.read(HEADER_SIZE)
+    if len(data) == HEADER_SIZE and data[:8] == HEADER_MAGIC:
+        if data[10] != kind or data[11] != payload_len:
+            raise ValueError('file has a different header')
+        return True
+    elif header and len(data) > 0:
+        raise ValueError('cannot add a header to a file without one')
+    return False
+
+
+def _check_set(id_: int, intervals: List[Tuple[int, int]]) -> None:
+    """Check that intervals are non-empty, sorted and non-overlapping"""
+    prev = None
+    for i, (a, b) in enumerate(intervals):
+        assert b > a, 'id {} interval {}: invalid interval: ({}, {})'.format(
+            id_, i, a, b)
+        if prev is not None:
+            assert a >= prev[0], \
+                'id {} interval {}: interval ({}, {}) is not sorted'.format(
+                    id_, i, a, b)
+            assert a >= prev[1], \
+                'id {} interval {}: interval ({}, {}) overlaps ({}, {})'.format(
+                    id_, i, a, b, prev[0], prev[1])
+        prev = (a, b)
+
+
+def _normalize_set(
+    intervals: List[Tuple[int, int]]
+) -> List[Tuple[int, int]]:
+    """Sort intervals and merge the ones that overlap"""
+    result: List[Tuple[int, int]] = []
+    for a, b in sorted(intervals):
+        assert b > a, 'invalid interval: ({}, {})'.format(a, b)
+        if len(result) > 0 and a < result[-1][1]:
+            result[-1] = (result[-1][0], max(b, result[-1][1]))
+        else:
+            result.append((a, b))
+    return result
+
+
+def _strip_index(fp: BinaryIO,
+                 records_start: int) -> Optional[List[IndexEntry]]:
+    """
+    Remove the index from the end of a file and return its entries, leaving
+    the file positioned after the last record. The index has to start after
+    the header (at records_start) and hold a whole number of entries.
+    """
+    fp.seek(0, os.SEEK_END)
+    length = fp.tell()
+    if length < INDEX_TRAILER_SIZE:
+        return None
+    fp.seek(length - INDEX_TRAILER_SIZE)
+    trailer = fp.read(INDEX_TRAILER_SIZE)
+    if trailer[8:] != INDEX_MAGIC:
+        return None
+    index_offset = int.from_bytes(trailer[:8], byteorder='little')
+    index_end = length - INDEX_TRAILER_SIZE
+    if not records_start <= index_offset <= index_end \
+            or (index_end - index_offset) % 16 != 0:
+        raise ValueError('file has an invalid index')
+    fp.seek(index_offset)
+    data = fp.read(index_end - index_offset)
+    entries = []
+    for i in range(0, len(data), 16):
+        entries.append((
+            int.from_bytes(data[i:i + 4], byteorder='little'),
+            int.from_bytes(data[i + 4:i + 8], byteorder='little'),
+            int.from_bytes(data[i + 8:i + 16], byteorder='little')))
+    fp.truncate(index_offset)
+    fp.seek(index_offset)
+    return entries
+
+
+def _scan_index(fp: BinaryIO, interval_size: int) -> List[IndexEntry]:
+    """
+    Rebuild the index entries of a file without an index from its records,
+    leaving the file positioned after the last record.
+    """
+    fp.seek(0, os.SEEK_END)
+    length = fp.tell()
+    fp.seek(0)
+    offset = 0
+    if length >= HEADER_SIZE and fp.read(len(HEADER_MAGIC)) == HEADER_MAGIC:
+        offset = HEADER_SIZE
+    entries = []
+    while offset < length:
+        fp.seek(offset)
+        data = fp.read(8)
+        if len(data) < 8:
+            break
+        n = int.from_bytes(data[4:], byteorder='little')
+        entries.append((int.from_bytes(data[:4], byteorder='little'), n,
+                        offset + 8))
+        offset += 8 + n * interval_size
+    if offset != length:
+        raise ValueError('file ends with a truncated record')
+    fp.seek(length)
+    return entries
+
+
+def _write_index(fp: BinaryIO, entries: List[IndexEntry]) -> None:
+    index_offset = fp.tell()
+    for id_, n, offset in sorted(entries, key=lambda x: x[0]):
+        fp.write(_fmt_u32(id_))
+        fp.write(_fmt_u32(n))
+        fp.write(_fmt_u64(offset))
+    fp.write(_fmt_u64(index_offset))
+    fp.write(INDEX_MAGIC)
+
 
 class IntervalSetMappingWriter(object):
 
-    def __init__(self, path: str, append: bool = False):
-        mode = 'ab' if append else 'wb'
+    def __init__(self, path: str, append: bool = False, index: bool = False,
+                 header: bool = False, repair: bool = False):
+        """
+        Unsorted or overlapping intervals are rejected, unless repair is set,
+        in which case they are sorted and merged.
+        """
+        mode = 'r+b' if append and os.path.exists(path) else 'wb'
         self._fp: Optional[BinaryIO] = open(path, mode)
         self._path = path
+        self._repair = repair
+        self._index: Optional[List[IndexEntry]] = [] if index else None
+        if mode == 'r+b':
+            has_header = _check_header(self._fp, KIND_SET, 0, header)
+            # Appending to an indexed file keeps it indexed
+            entries = _strip_index(
+                self._fp, HEADER_SIZE if has_header else 0)
+            if entries is not None:
+                self._index = entries
+            elif index:
+                # Index the records that are already in the file
+                self._index = _scan_index(self._fp, 8)
+        if header and self._fp.tell() == 0:
+            self._fp.write(_fmt_header(
+                KIND_SET, 0, FLAG_SORTED | FLAG_NON_OVERLAPPING))
 
     def __enter__(self) -> 'IntervalSetMappingWriter':
         return self
@@ -14,31 +178,50 @@ class IntervalSetMappingWriter(object):
     def __exit__(self, type, value, tb):
         self.close()
 
-    def __fmt_u32(self, v: int) -> bytes:
-        return v.to_bytes(4, byteorder='little')
-
     def write(self, id_: int, intervals: List[Tuple[int, int]]) -> None:
         assert self._fp is not None
-        self._fp.write(self.__fmt_u32(id_))
-        self._fp.write(self.__fmt_u32(len(intervals)))
+        if self._repair:
+            intervals = _normalize_set(intervals)
+        else:
+            _check_set(id_, intervals)
+        self._fp.write(_fmt_u32(id_))
+        self._fp.write(_fmt_u32(len(intervals)))
+        if self._index is not None:
+            self._index.append((id_, len(intervals), self._fp.tell()))
         for a, b in intervals:
-            assert b > a, 'invalid interval: ({}, {})'.format(a, b)
-            self._fp.write(self.__fmt_u32(a))
-            self._fp.write(self.__fmt_u32(b))
+            self._fp.write(_fmt_u32(a))
+            self._fp.write(_fmt_u32(b))
 
     def close(self) -> None:
         if self._fp is not None:
+            if self._index is not None:
+                _write_index(self._fp, self._index)
             self._fp.close()
             self._fp = None
 
 
 class IntervalListMappingWriter(object):
 
-    def __init__(self, path: str, payload_len: int, append: bool = False):
-        mode = 'ab' if append else 'wb'
+    def __init__(self, path: str, payload_len: int, append: bool = False,
+                 index: bool = False, header: bool = False):
+        mode = 'r+b' if append and os.path.exists(path) else 'wb'
         self._fp: Optional[BinaryIO] = open(path, mode)
         self._path = path
         self._payload_len = payload_len
+        self._index: Optional[List[IndexEntry]] = [] if index else None
+        if mode == 'r+b':
+            has_header = _check_header(
+                self._fp, KIND_LIST, payload_len, header)
+            # Appending to an indexed file keeps it indexed
+            entries = _strip_index(
+                self._fp, HEADER_SIZE if has_header else 0)
+            if entries is not None:
+                self._index = entries
+            elif index:
+                # Index the records that are already in the file
+                self._index = _scan_index(self._fp, 8 + payload_len)
+        if header and self._fp.tell() == 0:
+            self._fp.write(_fmt_header(KIND_LIST, payload_len, FLAG_SORTED))
 
     def __enter__(self) -> 'IntervalListMappingWriter':
         return self
@@ -46,23 +229,24 @@ class IntervalListMappingWriter(object):
     def __exit__(self, type, value, tb) -> None:
         self.close()
 
-    def __fmt_u32(self, v: int) -> bytes:
-        return v.to_bytes(4, byteorder='little')
-
     def __fmt_payload(self, v: int) -> bytes:
         return v.to_bytes(self._payload_len, byteorder='little')
 
     def write(self, id_: int, intervals: List[Tuple[int, int, int]]) -> None:
         assert self._fp is not None
-        self._fp.write(self.__fmt_u32(id_))
-        self._fp.write(self.__fmt_u32(len(intervals)))
+        self._fp.write(_fmt_u32(id_))
+        self._fp.write(_fmt_u32(len(intervals)))
+        if self._index is not None:
+            self._index.append((id_, len(intervals), self._fp.tell()))
         for a, b, c in intervals:
             assert b > a, 'invalid interval: ({}, {})'.format(a, b)
-            self._fp.write(self.__fmt_u32(a))
-            self._fp.write(self.__fmt_u32(b))
+            self._fp.write(_fmt_u32(a))
+            self._fp.write(_fmt_u32(b))
             self._fp.write(self.__fmt_payload(c))
 
     def close(self) -> None:
         if self._fp is not None:
+            if self._index is not None:
+                _write_index(self._fp, self._index)
             self._fp.close()
             self._fp = None
@@ -23,7 +23,8 @@ setup(
     version='0.2.0',
     classifiers=[],
     packages=['rs_intervalset'],
-    rust_extensions=[RustExtension('rs_intervalset.rs_intervalset', 'Cargo.toml')],
+    rust_extensions=[RustExtension('rs_intervalset.rs_intervalset', 'Cargo.toml',
+                                   features=['extension-module'])],
     install_requires=install_requires,
     tests_require=tests_require,
     setup_requires=setup_requires,
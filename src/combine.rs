@@ -0,0 +1,282 @@
+/*
+* Combined mappings
+*
+* Views over several mappings that answer the same queries as an IntervalSetMapping. The
+* intervals for an id are combined as they are read from the files, without materializing the
+* intervals of each mapping.
+*/
+
+use std::collections::BTreeSet;
+use std::iter;
+use std::sync::Arc;
+
+use common::*;
+use error::{Error, Result};
+use ilistmap::MmapIntervalListMapping;
+use isetmap::MmapIntervalSetMapping;
+use ops;
+use payload::PayloadPredicate;
+
+pub type Intervals<'a> = Box<dyn Iterator<Item = Interval> + 'a>;
+
+// A mapping from ids to sorted, non-overlapping intervals that are computed when they are read.
+// Implementations provide the ids and the intervals for an id; the queries are built on them.
+pub trait CombinedMapping {
+
+    // The ids, in ascending order
+    fn get_ids(&self) -> &[Id];
+
+    // Iterate over the intervals for an id
+    fn intervals(&self, id: Id) -> Result<Intervals<'_>>;
+
+    // Iterate over the intervals for an id that are needed to answer a query on [start, end).
+    // Only their parts in [start, end) have to match the intervals for the id, so the
+    // intervals at either end may be missing or cut short. By default, all of them are read.
+    fn intervals_in(&self, id: Id, _start: Value, _end: Value) -> Result<Intervals<'_>> {
+        self.intervals(id)
+    }
+
+    // The intervals needed to answer a query on a sorted list of intervals
+    fn intervals_for(&self, id: Id, intervals: &[Interval]) -> Result<Intervals<'_>> {
+        match span(intervals) {
+            Some((start, end)) => self.intervals_in(id, start, end),
+            None => self.intervals_in(id, 0, 0)
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.get_ids().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn has_id(&self, id: Id) -> bool {
+        self.get_ids().binary_search(&id).is_ok()
+    }
+
+    fn sum(&self) -> u64 {
+        self.get_ids().iter().fold(0u64, |total, &id| {
+            total + self.intervals(id).map_or(
+                0, |ints| ints.fold(0u64, |acc, int| acc + (int.1 - int.0) as u64))
+        })
+    }
+
+    // Get the number of intervals for an id
+    fn get_interval_count(&self, id: Id) -> Result<usize> {
+        Ok(self.intervals(id)?.count())
+    }
+
+    fn get_intervals(&self, id: Id) -> Result<Vec<Interval>> {
+        Ok(self.intervals(id)?.collect())
+    }
+
+    // Get whether a target is in any of the intervals
+    fn is_contained(&self, id: Id, target: Value) -> Result<bool> {
+        Ok(self.intervals_in(id, target, target.saturating_add(1))?.take_while(
+            |int| int.0 <= target).any(|int| int.1 > target))
+    }
+
+    // Get whether start and end intersect with any of the intervals
+    fn has_intersection(&self, id: Id, start: Value, end: Value) -> Result<bool> {
+        Ok(ops::intersect(
+            self.intervals_in(id, start, end)?, iter::once((start, end))).next().is_some())
+    }
+
+    // Intersect a sorted list of intervals
+    fn intersect(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        Ok(ops::intersect(self.intervals_for(id, intervals)?, intervals.iter().cloned()).collect())
+    }
+
+    // Intersect and then sum
+    fn intersect_sum(&self, id: Id, intervals: &[Interval]) -> Result<u64> {
+        Ok(ops::intersect(self.intervals_for(id, intervals)?, intervals.iter().cloned()).fold(
+            0u64, |acc, int| acc + (int.1 - int.0) as u64))
+    }
+
+    // Minus this from intervals
+    fn minus(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        Ok(ops::minus(intervals.iter().cloned(), self.intervals_for(id, intervals)?).collect())
+    }
+}
+
+fn union_ids<I: Iterator<Item = Id>>(ids: impl Iterator<Item = I>) -> Vec<Id> {
+    ids.flatten().collect::<BTreeSet<_>>().into_iter().collect()
+}
+
+// The union of the intervals whose payload matches in several list mappings, with overlapping
+// intervals (or those less than fuzz apart) merged. An id is present if any mapping has it.
+// As in MmapUnionIlistsToISetMapping, is_contained, has_intersection, intersect and
+// intersect_sum look at the intervals before they are merged with fuzz, so a gap less than
+// fuzz wide is not contained in the union, and merge the parts that they return instead.
+pub struct ListUnionMapping {
+    mappings: Vec<Arc<MmapIntervalListMapping>>,
+    payload: PayloadPredicate,
+    fuzz: Value,
+    ids: Vec<Id>,
+}
+
+impl ListUnionMapping {
+
+    pub fn new(
+        mappings: Vec<Arc<MmapIntervalListMapping>>, payload: PayloadPredicate, fuzz: Value
+    ) -> Self {
+        let ids = union_ids(mappings.iter().map(|m| m.ids()));
+        ListUnionMapping {mappings, payload, fuzz, ids}
+    }
+
+    // The union of the intervals for an id that overlap [start, end), before merging those
+    // less than fuzz apart
+    fn unmerged_in(&self, id: Id, start: Value, end: Value) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let iters = self.mappings.iter().filter_map(
+            |m| m.intervals_in(id, start, end, &self.payload).ok()
+        ).collect();
+        Ok(Box::new(ops::coalesce(ops::merge(iters), 0)))
+    }
+
+    // The parts of a sorted list of intervals that are in the union, with those less than fuzz
+    // apart merged
+    fn intersect_unmerged<'a>(
+        &'a self, id: Id, intervals: &'a [Interval]
+    ) -> Result<impl Iterator<Item = Interval> + 'a> {
+        let (start, end) = span(intervals).unwrap_or((0, 0));
+        Ok(ops::coalesce(ops::intersect(
+            self.unmerged_in(id, start, end)?, intervals.iter().cloned()), self.fuzz))
+    }
+}
+
+impl CombinedMapping for ListUnionMapping {
+
+    fn get_ids(&self) -> &[Id] {
+        &self.ids
+    }
+
+    fn intervals(&self, id: Id) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let iters = self.mappings.iter().filter_map(
+            |m| m.intervals(id, &self.payload).ok()
+        ).collect();
+        Ok(Box::new(ops::coalesce(ops::merge(iters), self.fuzz)))
+    }
+
+    // Intervals less than fuzz outside [start, end) can be merged with those inside it
+    fn intervals_in(&self, id: Id, start: Value, end: Value) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let (start, end) = (start.saturating_sub(self.fuzz), end.saturating_add(self.fuzz));
+        let iters = self.mappings.iter().filter_map(
+            |m| m.intervals_in(id, start, end, &self.payload).ok()
+        ).collect();
+        Ok(Box::new(ops::coalesce(ops::merge(iters), self.fuzz)))
+    }
+
+    fn is_contained(&self, id: Id, target: Value) -> Result<bool> {
+        Ok(self.unmerged_in(id, target, target.saturating_add(1))?.take_while(
+            |int| int.0 <= target).any(|int| int.1 > target))
+    }
+
+    fn has_intersection(&self, id: Id, start: Value, end: Value) -> Result<bool> {
+        Ok(ops::intersect(
+            self.unmerged_in(id, start, end)?, iter::once((start, end))).next().is_some())
+    }
+
+    fn intersect(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        Ok(self.intersect_unmerged(id, intervals)?.collect())
+    }
+
+    fn intersect_sum(&self, id: Id, intervals: &[Interval]) -> Result<u64> {
+        Ok(self.intersect_unmerged(id, intervals)?.fold(
+            0u64, |acc, int| acc + (int.1 - int.0) as u64))
+    }
+}
+
+// The union of several set mappings, with overlapping intervals merged. An id is present if any
+// mapping has it.
+pub struct SetUnionMapping {
+    mappings: Vec<Arc<MmapIntervalSetMapping>>,
+    ids: Vec<Id>,
+}
+
+impl SetUnionMapping {
+
+    pub fn new(mappings: Vec<Arc<MmapIntervalSetMapping>>) -> Self {
+        let ids = union_ids(mappings.iter().map(|m| m.ids()));
+        SetUnionMapping {mappings, ids}
+    }
+}
+
+impl CombinedMapping for SetUnionMapping {
+
+    fn get_ids(&self) -> &[Id] {
+        &self.ids
+    }
+
+    fn intervals(&self, id: Id) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let iters = self.mappings.iter().filter_map(|m| m.intervals(id).ok()).collect();
+        Ok(Box::new(ops::coalesce(ops::merge(iters), 0)))
+    }
+
+    fn intervals_in(&self, id: Id, start: Value, end: Value) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let iters = self.mappings.iter().filter_map(
+            |m| m.intervals_in(id, start, end).ok()
+        ).collect();
+        Ok(Box::new(ops::coalesce(ops::merge(iters), 0)))
+    }
+}
+
+// The intersection of several set mappings. An id is present if every mapping has it.
+pub struct SetIntersectionMapping {
+    mappings: Vec<Arc<MmapIntervalSetMapping>>,
+    ids: Vec<Id>,
+}
+
+impl SetIntersectionMapping {
+
+    pub fn new(mappings: Vec<Arc<MmapIntervalSetMapping>>) -> Self {
+        let ids = match mappings.split_first() {
+            Some((first, rest)) => first.ids().filter(
+                |&id| rest.iter().all(|m| m.has_id(id))
+            ).collect(),
+            None => vec![]
+        };
+        SetIntersectionMapping {mappings, ids}
+    }
+}
+
+impl CombinedMapping for SetIntersectionMapping {
+
+    fn get_ids(&self) -> &[Id] {
+        &self.ids
+    }
+
+    fn intervals(&self, id: Id) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let iters = self.mappings.iter().map(|m| m.intervals(id)).collect::<Result<_>>()?;
+        Ok(Box::new(ops::intersect_all(iters)))
+    }
+
+    fn intervals_in(&self, id: Id, start: Value, end: Value) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let iters = self.mappings.iter().map(
+            |m| m.intervals_in(id, start, end)
+        ).collect::<Result<_>>()?;
+        Ok(Box::new(ops::intersect_all(iters)))
+    }
+}
@@ -1,9 +1,14 @@
-
+use std::cmp::max;
 use std::collections::BTreeMap;
+use std::fs::File;
 use std::mem;
 use std::io::Cursor;
+use std::result;
+use std::str::FromStr;
 use byteorder::{ReadBytesExt, LittleEndian};
-use memmap::Mmap;
+use memmap::{MmapOptions, Mmap};
+
+use error::{self, Error};
 
 pub type Id = u32;
 pub type Value = u32;
@@ -12,33 +17,478 @@ pub type Payload = u64;
 
 pub const INTERVAL_SIZE: usize = 2 * mem::size_of::<Value>();
 
-pub fn mmap_read_u32(m: &Mmap, i: usize) -> u32 {
+/*
+* Optional header, written by the writers before the first record:
+*  - 8 byte magic
+*  - u16 (LE) format version
+*  - u8 kind (0 = IntervalSetMapping, 1 = IntervalListMapping)
+*  - u8 payload length in bytes
+*  - u8 value width in bytes
+*  - u8 flags (FLAG_SORTED | FLAG_NON_OVERLAPPING)
+*  - 2 reserved bytes
+*/
+pub const HEADER_MAGIC: &[u8] = b"RSINTVAL";
+pub const HEADER_SIZE: usize = 16;
+pub const FORMAT_VERSION: u16 = 1;
+pub const FLAG_SORTED: u8 = 0x1;
+pub const FLAG_NON_OVERLAPPING: u8 = 0x2;
+
+/*
+* Optional id index, written by the writers after the last record:
+*  - For each id (sorted by id):
+*     - u32 (LE) ID
+*     - u32 (LE) Number of intervals
+*     - u64 (LE) Offset of the first interval
+*  - u64 (LE) Offset of the first index entry
+*  - 8 byte magic
+*/
+pub const INDEX_MAGIC: &[u8] = b"RSIDX001";
+pub const INDEX_ENTRY_SIZE: usize = 2 * mem::size_of::<u32>() + mem::size_of::<u64>();
+pub const INDEX_TRAILER_SIZE: usize = mem::size_of::<u64>() + 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Set = 0,
+    List = 1
+}
+
+impl Kind {
+
+    fn from_u8(v: u8) -> Option<Kind> {
+        match v {
+            0 => Some(Kind::Set),
+            1 => Some(Kind::List),
+            _ => None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Set => "interval set mapping",
+            Kind::List => "interval list mapping"
+        }
+    }
+}
+
+pub fn mmap_read_u32(m: &[u8], i: usize) -> u32 {
     let mut rdr = Cursor::new(&m[i..i + mem::size_of::<u32>()]);
     rdr.read_u32::<LittleEndian>().unwrap()
 }
 
-pub fn mmap_read_payload(m: &Mmap, i: usize, n: usize) -> Payload {
+pub fn mmap_read_u64(m: &[u8], i: usize) -> u64 {
+    let mut rdr = Cursor::new(&m[i..i + mem::size_of::<u64>()]);
+    rdr.read_u64::<LittleEndian>().unwrap()
+}
+
+pub fn mmap_read_payload(m: &[u8], i: usize, n: usize) -> Payload {
     let mut res: Payload = 0;
     let bytes: &[u8] = &m[i..i+n];
-    for j in 0..n {
-        res |= (bytes[j] as u64) << (8 * j);
+    for (j, b) in bytes.iter().enumerate() {
+        res |= (*b as u64) << (8 * j);
+    }
+    res
+}
+
+// The bytes of a mapping: the file, followed by any records that were rebuilt while loading it.
+// Offsets past the end of the file point into the rebuilt records.
+pub struct Storage {
+    mmap: Option<Mmap>,
+    merged: Vec<u8>,
+}
+
+impl Storage {
+
+    pub fn new(mmap: Option<Mmap>, merged: Vec<u8>) -> Self {
+        Storage {mmap, merged}
+    }
+
+    pub fn mmap(&self) -> Option<&Mmap> {
+        self.mmap.as_ref()
+    }
+
+    // The bytes starting at offset i
+    pub fn bytes(&self, i: usize) -> &[u8] {
+        match self.mmap.as_ref() {
+            Some(m) if i < m.len() => &m[i..],
+            Some(m) => &self.merged[i - m.len()..],
+            None => &self.merged[i..]
+        }
+    }
+}
+
+// What to do when an id has more than one block of intervals, e.g., in a file that was appended to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Duplicates {
+    Error,
+    First,
+    Last,
+    Merge,  // Concatenate the blocks (merging overlapping intervals in a set)
+}
+
+impl FromStr for Duplicates {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Duplicates::Error),
+            "first" => Ok(Duplicates::First),
+            "last" => Ok(Duplicates::Last),
+            "merge" => Ok(Duplicates::Merge),
+            _ => Err(format!(
+                "duplicates must be 'error', 'first', 'last' or 'merge', not '{}'", s))
+        }
+    }
+}
+
+// Bounds on the length of the intervals to include, e.g., to drop short spurious detections.
+// Both bounds are inclusive, and the default includes every interval.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct LengthFilter {
+    pub min_len: Value,
+    pub max_len: Option<Value>,
+}
+
+impl LengthFilter {
+
+    pub fn new(min_len: Value, max_len: Option<Value>) -> Self {
+        LengthFilter {min_len, max_len}
+    }
+
+    pub fn is_any(&self) -> bool {
+        self.min_len == 0 && self.max_len.is_none()
+    }
+
+    pub fn matches(&self, int: Interval) -> bool {
+        let len = int.1.saturating_sub(int.0);
+        len >= self.min_len && self.max_len.map_or(true, |max_len| len <= max_len)
+    }
+}
+
+// Locations of the intervals for each id, either parsed from the records or read from the index
+pub enum IdOffsets {
+    Parsed(BTreeMap<Id, (usize, usize)>),
+    Indexed(usize, usize)   // Offset of the first index entry and number of entries
+}
+
+impl IdOffsets {
+
+    pub fn len(&self) -> usize {
+        match self {
+            IdOffsets::Parsed(offsets) => offsets.len(),
+            IdOffsets::Indexed(_, count) => *count
+        }
+    }
+
+    pub fn get(&self, m: Option<&Mmap>, id: Id) -> Option<(usize, usize)> {
+        match self {
+            IdOffsets::Parsed(offsets) => offsets.get(&id).cloned(),
+            IdOffsets::Indexed(base, count) => {
+                let m = m.unwrap();
+                let mut min_idx = 0;
+                let mut max_idx = *count;
+                while max_idx > min_idx {
+                    let pivot = (min_idx + max_idx) / 2;
+                    let (pivot_id, offset) = read_index_entry(m, *base, pivot);
+                    if pivot_id == id {
+                        return Some(offset);
+                    } else if id < pivot_id {
+                        max_idx = pivot;
+                    } else {
+                        min_idx = pivot + 1;
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    pub fn iter<'a>(
+        &'a self, m: Option<&'a Mmap>
+    ) -> Box<dyn Iterator<Item = (Id, (usize, usize))> + 'a> {
+        match self {
+            IdOffsets::Parsed(offsets) => Box::new(offsets.iter().map(|(k, v)| (*k, *v))),
+            IdOffsets::Indexed(base, count) => {
+                let m = m.unwrap();
+                Box::new((0..*count).map(move |i| read_index_entry(m, *base, i)))
+            }
+        }
+    }
+}
+
+fn read_index_entry(m: &[u8], base: usize, i: usize) -> (Id, (usize, usize)) {
+    let entry = base + i * INDEX_ENTRY_SIZE;
+    (
+        mmap_read_u32(m, entry) as Id,
+        (
+            mmap_read_u64(m, entry + 2 * mem::size_of::<u32>()) as usize,
+            mmap_read_u32(m, entry + mem::size_of::<Id>()) as usize
+        )
+    )
+}
+
+// Find the first interval that breaks the set invariants (non-empty, sorted and non-overlapping)
+pub fn find_set_violation<I>(intervals: I) -> Option<(usize, String)>
+    where I: Iterator<Item = Interval>
+{
+    let mut prev: Option<Interval> = None;
+    for (i, (a, b)) in intervals.enumerate() {
+        if b <= a {
+            return Some((i, format!("invalid interval: ({}, {})", a, b)));
+        }
+        if let Some(prev) = prev {
+            if a < prev.0 {
+                return Some((i, format!("interval ({}, {}) is not sorted", a, b)));
+            }
+            if a < prev.1 {
+                return Some((i, format!(
+                    "interval ({}, {}) overlaps ({}, {})", a, b, prev.0, prev.1)));
+            }
+        }
+        prev = Some((a, b));
+    }
+    None
+}
+
+// The range from the first start to the furthest end, or None if there are no intervals
+pub fn span(intervals: &[Interval]) -> Option<Interval> {
+    let start = intervals.first()?.0;
+    Some((start, intervals.iter().map(|int| int.1).max().unwrap_or(start)))
+}
+
+// Sort intervals and merge the ones that overlap
+pub fn normalize_set(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.sort_unstable();
+    let mut res: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for int in intervals {
+        match res.last_mut() {
+            Some(last) if int.0 < last.1 => last.1 = max(last.1, int.1),
+            _ => res.push(int)
+        }
     }
     res
 }
 
-pub fn parse_offsets(m: &Mmap, payload_len: usize) -> Option<BTreeMap<Id, (usize, usize)>> {
-    let mut i = 0;
-    let mut id_offsets: BTreeMap<Id, (usize, usize)> = BTreeMap::new();
-    while i < m.len() {
+pub fn has_header_magic(m: &Mmap) -> bool {
+    m.len() >= HEADER_SIZE && &m[..HEADER_MAGIC.len()] == HEADER_MAGIC
+}
+
+pub fn header_kind(m: &Mmap) -> Option<Kind> {
+    if has_header_magic(m) { Kind::from_u8(m[10]) } else { None }
+}
+
+// Check the header, if there is one, and return the offset of the first record and the
+// payload length
+pub fn parse_header(
+    m: &Mmap, kind: Kind, payload_len: Option<usize>
+) -> Result<(usize, usize), String> {
+    if !has_header_magic(m) {
+        return match payload_len {
+            Some(payload_len) => Ok((0, payload_len)),
+            None => Err("payload_len is required for files without a header".to_owned())
+        };
+    }
+    let version = (m[8] as u16) | ((m[9] as u16) << 8);
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(format!("unsupported format version: {}", version));
+    }
+    let file_kind = match Kind::from_u8(m[10]) {
+        Some(file_kind) => file_kind,
+        None => return Err(format!("unknown mapping kind: {}", m[10]))
+    };
+    if file_kind != kind {
+        return Err(format!("file contains an {}, not an {}", file_kind.name(), kind.name()));
+    }
+    let file_payload_len = m[11] as usize;
+    if file_payload_len > mem::size_of::<Payload>()
+        || (kind == Kind::Set && file_payload_len != 0) {
+        return Err(format!("invalid payload length: {}", file_payload_len));
+    }
+    if let Some(payload_len) = payload_len {
+        if payload_len != file_payload_len {
+            return Err(format!(
+                "payload_len is {}, but the file has a payload length of {}",
+                payload_len, file_payload_len));
+        }
+    }
+    if m[12] as usize != mem::size_of::<Value>() {
+        return Err(format!("unsupported value width: {}", m[12]));
+    }
+    let required_flags = match kind {
+        Kind::Set => FLAG_SORTED | FLAG_NON_OVERLAPPING,
+        Kind::List => FLAG_SORTED
+    };
+    if m[13] & required_flags != required_flags {
+        return Err(format!("file is not flagged as valid for an {}", kind.name()));
+    }
+    Ok((HEADER_SIZE, file_payload_len))
+}
+
+// Scan the records and return the (id, (offset of the first interval, count)) of each one
+pub fn parse_offsets(
+    m: &Mmap, begin: usize, payload_len: usize
+) -> Option<Vec<(Id, (usize, usize))>> {
+    let mut i = begin;
+    let mut blocks = Vec::new();
+    while i + mem::size_of::<Id>() + mem::size_of::<u32>() <= m.len() {
         let id = mmap_read_u32(m, i) as Id;
         let n = mmap_read_u32(m, i + mem::size_of::<Id>()) as usize;
         i += mem::size_of::<Id>() + mem::size_of::<u32>();
-        id_offsets.insert(id, (i, n));
+        if n > (m.len() - i) / (INTERVAL_SIZE + payload_len) {
+            return None;
+        }
+        blocks.push((id, (i, n)));
         i += n * (INTERVAL_SIZE + payload_len);
     }
     if i != m.len() {
         None
     } else {
-        Some(id_offsets)
+        Some(blocks)
+    }
+}
+
+// Build the offsets for the blocks of each id, in file order. Merged blocks are appended to
+// merged, at offsets that start after the end of the file.
+fn resolve_duplicates(
+    m: &Mmap, kind: Kind, payload_len: usize, blocks: Vec<(Id, (usize, usize))>,
+    duplicates: Duplicates, merged: &mut Vec<u8>
+) -> Result<BTreeMap<Id, (usize, usize)>, String> {
+    let mut id_blocks: BTreeMap<Id, Vec<(usize, usize)>> = BTreeMap::new();
+    for (id, block) in blocks {
+        id_blocks.entry(id).or_default().push(block);
+    }
+    let mut id_offsets = BTreeMap::new();
+    for (id, blocks) in id_blocks {
+        let offset = if blocks.len() == 1 {
+            blocks[0]
+        } else {
+            match duplicates {
+                Duplicates::Error => return Err(format!(
+                    "id {} appears {} times (use duplicates='first', 'last' or 'merge')",
+                    id, blocks.len())),
+                Duplicates::First => blocks[0],
+                Duplicates::Last => blocks[blocks.len() - 1],
+                Duplicates::Merge => merge_blocks(m, kind, payload_len, &blocks, merged)
+            }
+        };
+        id_offsets.insert(id, offset);
+    }
+    Ok(id_offsets)
+}
+
+// Concatenate blocks and sort the result by start. Intervals in a set are also merged.
+fn merge_blocks(
+    m: &Mmap, kind: Kind, payload_len: usize, blocks: &[(usize, usize)], merged: &mut Vec<u8>
+) -> (usize, usize) {
+    let interval_size = INTERVAL_SIZE + payload_len;
+    let mut records: Vec<&[u8]> = Vec::new();
+    for &(offset, n) in blocks {
+        records.extend((0..n).map(|i| &m[offset + i * interval_size..][..interval_size]));
+    }
+    let base = m.len() + merged.len();
+    match kind {
+        Kind::Set => {
+            let intervals = normalize_set(
+                records.iter().map(|r| (mmap_read_u32(r, 0), mmap_read_u32(r, 4))).collect());
+            for (a, b) in intervals.iter() {
+                merged.extend_from_slice(&a.to_le_bytes());
+                merged.extend_from_slice(&b.to_le_bytes());
+            }
+            (base, intervals.len())
+        },
+        Kind::List => {
+            // Stable, so intervals with the same start keep their order
+            records.sort_by_key(|r| mmap_read_u32(r, 0));
+            for r in records.iter() {
+                merged.extend_from_slice(r);
+            }
+            (base, records.len())
+        }
+    }
+}
+
+// Validate the index at the end of the file, if there is one
+fn parse_index(
+    m: &Mmap, kind: Kind, begin: usize, payload_len: usize, duplicates: Duplicates,
+    merged: &mut Vec<u8>
+) -> Result<Option<IdOffsets>, String> {
+    if m.len() < begin + INDEX_TRAILER_SIZE
+        || &m[m.len() - INDEX_MAGIC.len()..] != INDEX_MAGIC {
+        return Ok(None);
+    }
+    let index_end = m.len() - INDEX_TRAILER_SIZE;
+    let base = mmap_read_u64(m, index_end) as usize;
+    if base < begin || base > index_end || (index_end - base) % INDEX_ENTRY_SIZE != 0 {
+        return Err("cannot parse index".to_owned());
+    }
+    let count = (index_end - base) / INDEX_ENTRY_SIZE;
+    let mut prev_id: Option<Id> = None;
+    let mut has_duplicates = false;
+    for i in 0..count {
+        let (id, (offset, n)) = read_index_entry(m, base, i);
+        if let Some(prev_id) = prev_id {
+            if prev_id > id {
+                return Err(format!("index is not sorted at id {}", id));
+            }
+            has_duplicates |= prev_id == id;
+        }
+        if offset < begin || offset > base
+            || n > (base - offset) / (INTERVAL_SIZE + payload_len) {
+            return Err(format!("index entry for id {} is out of range", id));
+        }
+        prev_id = Some(id);
+    }
+    if has_duplicates {
+        // Entries for the same id are in the order that the blocks were written
+        let blocks = (0..count).map(|i| read_index_entry(m, base, i)).collect();
+        Ok(Some(IdOffsets::Parsed(
+            resolve_duplicates(m, kind, payload_len, blocks, duplicates, merged)?)))
+    } else {
+        Ok(Some(IdOffsets::Indexed(base, count)))
+    }
+}
+
+// Use the index if the file has one, otherwise scan the records. Returns the offsets and the
+// bytes of any merged blocks, which belong after the end of the file.
+pub fn load_offsets(
+    m: &Mmap, kind: Kind, begin: usize, payload_len: usize, duplicates: Duplicates
+) -> Result<(IdOffsets, Vec<u8>), String> {
+    let mut merged = Vec::new();
+    let offsets = match parse_index(m, kind, begin, payload_len, duplicates, &mut merged)? {
+        Some(offsets) => offsets,
+        None => match parse_offsets(m, begin, payload_len) {
+            Some(blocks) => IdOffsets::Parsed(
+                resolve_duplicates(m, kind, payload_len, blocks, duplicates, &mut merged)?),
+            None => return Err("cannot parse offsets".to_owned())
+        }
+    };
+    Ok((offsets, merged))
+}
+
+// Map a file and load the offsets of its records. Returns the storage, the offsets and the
+// payload length.
+pub fn open_mapping(
+    path: &str, kind: Kind, payload_len: Option<usize>, duplicates: Duplicates
+) -> error::Result<(Storage, IdOffsets, usize)> {
+    let fh = File::open(path)?;
+    let length = fh.metadata()?.len() as usize;
+
+    // Empty file case
+    if length == 0 {
+        return Ok((
+            Storage::new(None, Vec::new()), IdOffsets::Parsed(BTreeMap::new()),
+            payload_len.unwrap_or(0)
+        ));
+    }
+
+    // The file must not be modified while it is mapped
+    let m = unsafe { MmapOptions::new().map(&fh)? };
+    let (begin, payload_len) = parse_header(&m, kind, payload_len).map_err(Error::Format)?;
+
+    // The header, records and index of a set are all made of 4 byte words
+    if kind == Kind::Set && length % mem::size_of::<u32>() != 0 {
+        return Err(Error::Format("file length is not a multiple of 4".to_owned()));
     }
+    let (offsets, merged) = load_offsets(&m, kind, begin, payload_len, duplicates)
+        .map_err(Error::Format)?;
+    Ok((Storage::new(Some(m), merged), offsets, payload_len))
 }
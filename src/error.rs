@@ -0,0 +1,46 @@
+/*
+* Errors returned by the mappings
+*/
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use common::Id;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(String),         // The file is not a valid mapping
+    IdNotFound(Id),
+    IndexOutOfRange(usize),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Format(msg) => f.write_str(msg),
+            Error::IdNotFound(_) => f.write_str("id not found"),
+            Error::IndexOutOfRange(_) => f.write_str("index out of range"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,262 @@
+/*
+* Query expressions
+*
+* A tree of operations over mappings that is evaluated lazily, one id at a time. Evaluating an
+* id chains the streaming operations in ops over the intervals read from the files, so no
+* intermediate results are materialized. Expressions answer the same queries as the combined
+* mappings, and can be shared between several larger expressions.
+*/
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter;
+use std::sync::Arc;
+
+use combine::{CombinedMapping, Intervals};
+use common::*;
+use error::{Error, Result};
+use ilistmap::MmapIntervalListMapping;
+use isetmap::MmapIntervalSetMapping;
+use ops;
+use payload::PayloadPredicate;
+
+pub enum Node {
+    Set(Arc<MmapIntervalSetMapping>),
+    List(Arc<MmapIntervalListMapping>, PayloadPredicate),
+    Mapping(Arc<dyn CombinedMapping + Send + Sync>),
+    And(Arc<Expr>, Arc<Expr>),
+    Or(Arc<Expr>, Arc<Expr>),
+    Minus(Arc<Expr>, Arc<Expr>),
+    Dilate(Arc<Expr>, Value),
+    Erode(Arc<Expr>, Value),
+    Coalesce(Arc<Expr>, Value),
+    FilterLength(Arc<Expr>, Value, Option<Value>),
+}
+
+pub struct Expr {
+    node: Node,
+    ids: Vec<Id>,
+}
+
+fn intersect_ids(a: &[Id], b: &[Id]) -> Vec<Id> {
+    let mut res = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                res.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    res
+}
+
+fn union_ids(a: &[Id], b: &[Id]) -> Vec<Id> {
+    let mut res = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => {
+                res.push(a[i]);
+                i += 1;
+            },
+            Ordering::Greater => {
+                res.push(b[j]);
+                j += 1;
+            },
+            Ordering::Equal => {
+                res.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    res.extend_from_slice(&a[i..]);
+    res.extend_from_slice(&b[j..]);
+    res
+}
+
+impl Expr {
+
+    // The intervals of a set mapping
+    pub fn set(mapping: Arc<MmapIntervalSetMapping>) -> Self {
+        let ids = mapping.ids().collect();
+        Expr {node: Node::Set(mapping), ids}
+    }
+
+    // The intervals of a list mapping whose payload matches, with overlapping intervals merged
+    pub fn list(mapping: Arc<MmapIntervalListMapping>, payload: PayloadPredicate) -> Self {
+        let ids = mapping.ids().collect();
+        Expr {node: Node::List(mapping, payload), ids}
+    }
+
+    // The intervals of a combined mapping
+    pub fn mapping(mapping: Arc<dyn CombinedMapping + Send + Sync>) -> Self {
+        let ids = mapping.get_ids().to_vec();
+        Expr {node: Node::Mapping(mapping), ids}
+    }
+
+    // Present for ids that both sides have
+    pub fn and(a: impl Into<Arc<Expr>>, b: impl Into<Arc<Expr>>) -> Self {
+        let (a, b) = (a.into(), b.into());
+        let ids = intersect_ids(&a.ids, &b.ids);
+        Expr {node: Node::And(a, b), ids}
+    }
+
+    // Present for ids that either side has
+    pub fn or(a: impl Into<Arc<Expr>>, b: impl Into<Arc<Expr>>) -> Self {
+        let (a, b) = (a.into(), b.into());
+        let ids = union_ids(&a.ids, &b.ids);
+        Expr {node: Node::Or(a, b), ids}
+    }
+
+    // Present for the ids that a has
+    pub fn minus(a: impl Into<Arc<Expr>>, b: impl Into<Arc<Expr>>) -> Self {
+        let (a, b) = (a.into(), b.into());
+        let ids = a.ids.clone();
+        Expr {node: Node::Minus(a, b), ids}
+    }
+
+    // Extend each interval by amount at both ends
+    pub fn dilate(e: impl Into<Arc<Expr>>, amount: Value) -> Self {
+        let e = e.into();
+        let ids = e.ids.clone();
+        Expr {node: Node::Dilate(e, amount), ids}
+    }
+
+    // Shrink each interval by amount at both ends, dropping those that vanish
+    pub fn erode(e: impl Into<Arc<Expr>>, amount: Value) -> Self {
+        let e = e.into();
+        let ids = e.ids.clone();
+        Expr {node: Node::Erode(e, amount), ids}
+    }
+
+    // Merge intervals that are less than gap apart
+    pub fn coalesce(e: impl Into<Arc<Expr>>, gap: Value) -> Self {
+        let e = e.into();
+        let ids = e.ids.clone();
+        Expr {node: Node::Coalesce(e, gap), ids}
+    }
+
+    // Keep the intervals whose length is at least min_len and, if given, at most max_len
+    pub fn filter_length(
+        e: impl Into<Arc<Expr>>, min_len: Value, max_len: Option<Value>
+    ) -> Self {
+        let e = e.into();
+        let ids = e.ids.clone();
+        Expr {node: Node::FilterLength(e, min_len, max_len), ids}
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    // The intervals of a child, or none if it does not have the id
+    fn intervals_or_empty(e: &Expr, id: Id) -> Result<Intervals<'_>> {
+        match e.intervals(id) {
+            Err(Error::IdNotFound(_)) => Ok(Box::new(iter::empty())),
+            res => res
+        }
+    }
+
+    // Same as intervals_or_empty, for the intervals needed on [start, end)
+    fn intervals_in_or_empty(e: &Expr, id: Id, start: Value, end: Value) -> Result<Intervals<'_>> {
+        match e.intervals_in(id, start, end) {
+            Err(Error::IdNotFound(_)) => Ok(Box::new(iter::empty())),
+            res => res
+        }
+    }
+}
+
+impl CombinedMapping for Expr {
+
+    fn get_ids(&self) -> &[Id] {
+        &self.ids
+    }
+
+    fn intervals(&self, id: Id) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        Ok(match &self.node {
+            Node::Set(m) => Box::new(m.intervals(id)?),
+            Node::List(m, payload) => Box::new(ops::coalesce(m.intervals(id, payload)?, 0)),
+            Node::Mapping(m) => m.intervals(id)?,
+            Node::And(a, b) => Box::new(ops::intersect(a.intervals(id)?, b.intervals(id)?)),
+            Node::Or(a, b) => Box::new(ops::coalesce(ops::merge(vec![
+                Expr::intervals_or_empty(a, id)?, Expr::intervals_or_empty(b, id)?
+            ]), 0)),
+            Node::Minus(a, b) => Box::new(
+                ops::minus(a.intervals(id)?, Expr::intervals_or_empty(b, id)?)),
+            Node::Dilate(e, amount) => Box::new(ops::dilate(e.intervals(id)?, *amount)),
+            Node::Erode(e, amount) => Box::new(ops::erode(e.intervals(id)?, *amount)),
+            Node::Coalesce(e, gap) => Box::new(ops::coalesce(e.intervals(id)?, *gap)),
+            Node::FilterLength(e, min_len, max_len) => Box::new(
+                ops::filter_length(e.intervals(id)?, *min_len, *max_len)),
+        })
+    }
+
+    // The window is passed down to the mappings, widened by the amount that dilate, erode and
+    // coalesce can move the intervals near it. Filtering by length needs whole intervals, which
+    // a window can cut short, so it reads all of the intervals below it.
+    fn intervals_in(&self, id: Id, start: Value, end: Value) -> Result<Intervals<'_>> {
+        if !self.has_id(id) {
+            return Err(Error::IdNotFound(id));
+        }
+        let widen = |amount: Value| (start.saturating_sub(amount), end.saturating_add(amount));
+        Ok(match &self.node {
+            Node::Set(m) => Box::new(m.intervals_in(id, start, end)?),
+            Node::List(m, payload) => Box::new(
+                ops::coalesce(m.intervals_in(id, start, end, payload)?, 0)),
+            Node::Mapping(m) => m.intervals_in(id, start, end)?,
+            Node::And(a, b) => Box::new(ops::intersect(
+                a.intervals_in(id, start, end)?, b.intervals_in(id, start, end)?)),
+            Node::Or(a, b) => Box::new(ops::coalesce(ops::merge(vec![
+                Expr::intervals_in_or_empty(a, id, start, end)?,
+                Expr::intervals_in_or_empty(b, id, start, end)?
+            ]), 0)),
+            Node::Minus(a, b) => Box::new(ops::minus(
+                a.intervals_in(id, start, end)?,
+                Expr::intervals_in_or_empty(b, id, start, end)?)),
+            Node::Dilate(e, amount) => {
+                let (start, end) = widen(*amount);
+                Box::new(ops::dilate(e.intervals_in(id, start, end)?, *amount))
+            },
+            Node::Erode(e, amount) => {
+                let (start, end) = widen(*amount);
+                Box::new(ops::erode(e.intervals_in(id, start, end)?, *amount))
+            },
+            Node::Coalesce(e, gap) => {
+                let (start, end) = widen(*gap);
+                Box::new(ops::coalesce(e.intervals_in(id, start, end)?, *gap))
+            },
+            Node::FilterLength(..) => self.intervals(id)?,
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.node {
+            Node::Set(_) => write!(f, "set"),
+            Node::List(_, PayloadPredicate::Equals(0, 0)) => write!(f, "list"),
+            Node::List(_, PayloadPredicate::Equals(mask, value)) =>
+                write!(f, "list[mask={:#x},value={:#x}]", mask, value),
+            Node::List(_, payload) => write!(f, "list[{}]", payload),
+            Node::Mapping(_) => write!(f, "mapping"),
+            Node::And(a, b) => write!(f, "({} & {})", a, b),
+            Node::Or(a, b) => write!(f, "({} | {})", a, b),
+            Node::Minus(a, b) => write!(f, "({} - {})", a, b),
+            Node::Dilate(e, amount) => write!(f, "dilate({}, {})", e, amount),
+            Node::Erode(e, amount) => write!(f, "erode({}, {})", e, amount),
+            Node::Coalesce(e, gap) => write!(f, "coalesce({}, {})", e, gap),
+            Node::FilterLength(e, min_len, None) => write!(f, "filter_length({}, {})", e, min_len),
+            Node::FilterLength(e, min_len, Some(max_len)) =>
+                write!(f, "filter_length({}, {}, {})", e, min_len, max_len),
+        }
+    }
+}
@@ -4,324 +4,569 @@
 * Maintain a mapping from ids to lists of intervals. These intervals can overlap and are defined
 * as [start, end). The intervals are sorted by start. Intervals have up to 64bits of payload.
 */
-use pyo3::prelude::*;
-use pyo3::exceptions;
-use std::cmp::{max, min};
-use std::collections::BTreeMap;
+use std::cmp::{max, min, Ordering};
+use std::collections::{HashMap, VecDeque};
 use std::mem;
-use std::fs::File;
-use memmap::{MmapOptions, Mmap};
+use std::sync::{Arc, Mutex};
 
 use common::*;
+use error::{Error, Result};
+use ops;
+use payload::PayloadPredicate;
 
-type IntervalAndPayload = (Value, Value, Payload);
+pub type IntervalAndPayload = (Value, Value, Payload);
 
-struct _MmapIntervalListMapping {
-    data: Option<Mmap>,
-    offsets: BTreeMap<Id, (usize, usize)>,
-    payload_len: usize
+// The most sampled maximum ends that are kept in memory, across all ids (16 MiB)
+const MAX_ENDS_CACHE_LEN: usize = 1 << 22;
+
+// The most sampled maximum ends for one id (4 MiB). Larger ids are sampled more sparsely.
+const MAX_ENDS_PER_ID: usize = 1 << 20;
+
+// The fewest intervals between samples, so that small ids take little memory
+const MIN_MAX_ENDS_BLOCK_LEN: usize = 64;
+
+// The running maximum of the interval ends for an id, sampled at the end of each block of
+// block_len intervals (and at the last interval), i.e., ends[k] is the end that extends
+// furthest among the first (k + 1) * block_len intervals
+struct MaxEnds {
+    block_len: usize,
+    ends: Vec<Value>,
+}
+
+// The sampled maximum ends of the most recently queried ids. Once the cache holds more than
+// MAX_ENDS_CACHE_LEN values, ids are dropped in clock (second chance) order: an id that was
+// used since the clock last passed it is kept for another round.
+#[derive(Default)]
+struct MaxEndsCache {
+    entries: HashMap<Id, (Arc<MaxEnds>, bool)>,     // Ends and whether they were used
+    clock: VecDeque<Id>,
+    len: usize,
+}
+
+impl MaxEndsCache {
+
+    fn get(&mut self, id: Id) -> Option<Arc<MaxEnds>> {
+        self.entries.get_mut(&id).map(|entry| {
+            entry.1 = true;
+            entry.0.clone()
+        })
+    }
+
+    fn insert(&mut self, id: Id, ends: Arc<MaxEnds>) {
+        if self.entries.contains_key(&id) {
+            return;
+        }
+        while self.len + ends.ends.len() > MAX_ENDS_CACHE_LEN {
+            let oldest = self.clock.pop_front().unwrap();
+            let used = {
+                let entry = self.entries.get_mut(&oldest).unwrap();
+                mem::replace(&mut entry.1, false)
+            };
+            if used {
+                self.clock.push_back(oldest);
+            } else {
+                self.len -= self.entries.remove(&oldest).unwrap().0.ends.len();
+            }
+        }
+        self.len += ends.ends.len();
+        self.clock.push_back(id);
+        self.entries.insert(id, (ends, false));
+    }
+}
+
+pub struct MmapIntervalListMapping {
+    data: Storage,
+    offsets: IdOffsets,
+    payload_len: usize,
+    max_ends: Mutex<MaxEndsCache>,     // Computed as ids are queried
 }
 
-impl _MmapIntervalListMapping {
+impl MmapIntervalListMapping {
+
+    // The payload length is only required for files without a header
+    pub fn open(path: &str, payload_len: Option<usize>, duplicates: Duplicates) -> Result<Self> {
+        let (data, offsets, payload_len) = open_mapping(
+            path, Kind::List, payload_len, duplicates)?;
+        Ok(MmapIntervalListMapping {data, offsets, payload_len, max_ends: Default::default()})
+    }
+
+    fn get_offsets(&self, id: Id) -> Result<(usize, usize)> {
+        self.offsets.get(self.data.mmap(), id).ok_or(Error::IdNotFound(id))
+    }
+
+    fn iter_offsets(&self) -> Box<dyn Iterator<Item = (Id, (usize, usize))> + '_> {
+        self.offsets.iter(self.data.mmap())
+    }
 
     fn read_interval(&self, i: usize) -> IntervalAndPayload {
-        let m = &self.data.as_ref().unwrap();
+        let m = self.data.bytes(i);
         (
-            mmap_read_u32(m, i),
-            mmap_read_u32(m, i + mem::size_of::<u32>()),
-            mmap_read_payload(m, i + 2 * mem::size_of::<u32>(), self.payload_len)
+            mmap_read_u32(m, 0),
+            mmap_read_u32(m, mem::size_of::<u32>()),
+            mmap_read_payload(m, 2 * mem::size_of::<u32>(), self.payload_len)
         )
     }
 
+    fn iter_intervals_with_payload(
+        &self, base_offset: usize, length: usize
+    ) -> impl Iterator<Item = IntervalAndPayload> + '_ {
+        let interval_payload_size = INTERVAL_SIZE + self.payload_len;
+        (0..length).map(move |i| self.read_interval(base_offset + i * interval_payload_size))
+    }
+
+    fn iter_intervals<'a>(
+        &'a self, base_offset: usize, length: usize, payload: &'a PayloadPredicate
+    ) -> impl Iterator<Item = Interval> + 'a {
+        self.iter_intervals_with_payload(base_offset, length).filter(
+            move |int_and_p| payload.matches(int_and_p.2)
+        ).map(|int_and_p| (int_and_p.0, int_and_p.1))
+    }
+
     fn read_intervals(
-        &self, base_offset: usize, length: usize, payload_mask: Payload, payload_value: Payload
+        &self, base_offset: usize, length: usize, payload: &PayloadPredicate
     ) -> Vec<Interval> {
-        let mut ret = Vec::new();
-        let interval_payload_size = INTERVAL_SIZE + self.payload_len;
-        for i in 0..length {
-            let int_and_p = self.read_interval(base_offset + i * interval_payload_size);
-            if (payload_mask & int_and_p.2) == payload_value {
-                ret.push((int_and_p.0, int_and_p.1));
+        self.iter_intervals(base_offset, length, payload).collect()
+    }
+
+    // The sampled running maximum of the interval ends for an id. Since the intervals are
+    // sorted by start, this bounds which of them can contain a point.
+    fn get_max_ends(&self, id: Id, base_offset: usize, length: usize) -> Arc<MaxEnds> {
+        if let Some(ends) = self.max_ends.lock().unwrap().get(id) {
+            return ends;
+        }
+        let block_len = max(
+            MIN_MAX_ENDS_BLOCK_LEN, (length + MAX_ENDS_PER_ID - 1) / MAX_ENDS_PER_ID);
+        let mut max_end = 0;
+        let mut ends = Vec::with_capacity((length + block_len - 1) / block_len);
+        for (i, int_and_p) in self.iter_intervals_with_payload(base_offset, length).enumerate() {
+            max_end = max(max_end, int_and_p.1);
+            if (i + 1) % block_len == 0 || i + 1 == length {
+                ends.push(max_end);
             }
         }
-        ret
+        let ends = Arc::new(MaxEnds {block_len, ends});
+        self.max_ends.lock().unwrap().insert(id, ends.clone());
+        ends
     }
 
-    fn read_intervals_with_payload(
-        &self, base_offset: usize, length: usize
-    ) -> Vec<IntervalAndPayload> {
-        let mut ret = Vec::new();
+    // The range of indices of the intervals that can overlap [start, end): those from the
+    // first that ends after start, or after an interval that does, and before the first that
+    // starts at or after end. Once the sampled maximum ends of the id are cached, this takes
+    // O(log n) plus a scan of one block, but the first query on an id (or one whose ends were
+    // evicted) reads all of its intervals.
+    fn overlap_range(
+        &self, id: Id, base_offset: usize, length: usize, start: Value, end: Value
+    ) -> (usize, usize) {
         let interval_payload_size = INTERVAL_SIZE + self.payload_len;
-        for i in 0..length {
-            ret.push(self.read_interval(base_offset + i * interval_payload_size));
+        let mut min_idx = 0;
+        let mut max_idx = length;
+        while min_idx < max_idx {
+            let pivot = (min_idx + max_idx) / 2;
+            if self.read_interval(base_offset + pivot * interval_payload_size).0 < end {
+                min_idx = pivot + 1;
+            } else {
+                max_idx = pivot;
+            }
+        }
+        let hi = min_idx;
+        if hi == 0 {
+            return (0, 0);
         }
-        ret
+        // The first block whose maximum end is past start. Every interval before it ends at
+        // or before start, so the first one in it that ends after start is where overlaps
+        // can begin.
+        let max_ends = self.get_max_ends(id, base_offset, length);
+        let block = max_ends.ends.binary_search_by(
+            |&e| if e > start { Ordering::Greater } else { Ordering::Less }
+        ).unwrap_err();
+        let block_start = min(block * max_ends.block_len, hi);
+        let block_end = min(block_start + max_ends.block_len, hi);
+        let lo = (block_start..block_end).find(
+            |&i| self.read_interval(base_offset + i * interval_payload_size).1 > start
+        ).unwrap_or(hi);
+        (lo, hi)
     }
-}
 
-#[pyclass]
-pub struct MmapIntervalListMapping {
-    _impl: _MmapIntervalListMapping
-}
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
 
-#[pymethods]
-impl MmapIntervalListMapping {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-    fn len(&self) -> PyResult<usize> {
-        Ok(self._impl.offsets.len())
+    // Iterate over the ids in ascending order
+    pub fn ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.iter_offsets().map(|(k, _)| k)
     }
 
-    fn get_ids(&self) -> PyResult<Vec<Id>> {
-        Ok(self._impl.offsets.keys().map(|k| *k).collect())
+    pub fn has_id(&self, id: Id) -> bool {
+        self.get_offsets(id).is_ok()
     }
 
-    fn has_id(&self, id: Id) -> PyResult<bool> {
-        Ok(self._impl.offsets.contains_key(&id))
+    pub fn get_payload_len(&self) -> usize {
+        self.payload_len
     }
 
-    fn sum(&self, payload_mask: Payload, payload_value: Payload) -> PyResult<u64> {
-        Ok(self._impl.offsets.iter().fold(
+    // Sum the lengths of the intervals whose payload and length match
+    pub fn sum(
+        &self, payload: &PayloadPredicate, lengths: LengthFilter
+    ) -> u64 {
+        self.iter_offsets().fold(
             0u64,
             |total, (_, (base_offset, length))| {
-                total + self._impl.read_intervals(
-                    *base_offset, *length, payload_mask, payload_value
-                ).iter().fold(0u64, |acc, int| acc + (int.1 - int.0) as u64)
+                total + self.iter_intervals(
+                    base_offset, length, payload
+                ).filter(
+                    |&int| lengths.matches(int)
+                ).fold(0u64, |acc, int| acc + (int.1 - int.0) as u64)
             }
-        ))
+        )
     }
 
-    // Get the number of intervals for an id
-    fn get_interval_count(
-        &self, id: Id, payload_mask: Payload, payload_value: Payload
-    ) -> PyResult<usize> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                if payload_mask == 0 && payload_value == 0 {
-                    Ok(*length)
-                } else {
-                    Ok(self._impl.read_intervals(
-                        *base_offset, *length, payload_mask, payload_value
-                    ).len())
+    // Sum the lengths of the intervals for an id, or for all ids if id is None, grouped by
+    // payload & payload_mask. Each group maps to its total length and number of intervals. If
+    // deoverlap is true, the overlapping intervals in a group are merged first, so that time
+    // is only counted once and a merged interval counts as one.
+    pub fn sum_by_payload(
+        &self, id: Option<Id>, payload_mask: Payload, deoverlap: bool
+    ) -> Result<HashMap<Payload, (u64, usize)>> {
+        let offsets: Vec<(usize, usize)> = match id {
+            Some(id) => vec![self.get_offsets(id)?],
+            None => self.iter_offsets().map(|(_, offsets)| offsets).collect()
+        };
+        let mut res: HashMap<Payload, (u64, usize)> = HashMap::new();
+        let mut add = |key: Payload, int: Interval| {
+            let entry = res.entry(key).or_insert((0, 0));
+            entry.0 += (int.1 - int.0) as u64;
+            entry.1 += 1;
+        };
+        for (base_offset, length) in offsets {
+            // The merged interval that each group has so far for this id
+            let mut current: HashMap<Payload, Interval> = HashMap::new();
+            for (start, end, payload) in self.iter_intervals_with_payload(base_offset, length) {
+                let key = payload & payload_mask;
+                if !deoverlap {
+                    add(key, (start, end));
+                    continue;
                 }
-            },
-            None => Err(exceptions::IndexError::py_err("id not found")),
+                match current.get_mut(&key) {
+                    Some(last) if start < last.1 => last.1 = max(last.1, end),
+                    Some(last) => add(key, mem::replace(last, (start, end))),
+                    None => {
+                        current.insert(key, (start, end));
+                    }
+                }
+            }
+            for (key, last) in current {
+                add(key, last);
+            }
         }
+        Ok(res)
     }
 
-    fn get_intervals(
-        &self, id: Id, payload_mask: Payload, payload_value: Payload,
-        use_default: bool
-    ) -> PyResult<Vec<Interval>> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                Ok(self._impl.read_intervals(
-                    *base_offset, *length, payload_mask, payload_value))
-            },
-            None => if use_default { Ok(vec![]) } else {
-                Err(exceptions::IndexError::py_err("id not found"))
-            },
+    // Get the number of intervals for an id
+    pub fn get_interval_count(
+        &self, id: Id, payload: &PayloadPredicate
+    ) -> Result<usize> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        if payload.is_any() {
+            Ok(length)
+        } else {
+            Ok(self.iter_intervals(base_offset, length, payload).count())
         }
     }
 
-    fn get_intervals_with_payload(
-        &self, id: Id, use_default: bool
-    ) -> PyResult<Vec<IntervalAndPayload>> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                Ok(self._impl.read_intervals_with_payload(*base_offset, *length))
-            },
-            None => if use_default { Ok(vec![]) } else {
-                Err(exceptions::IndexError::py_err("id not found"))
-            },
-        }
+    // Iterate over the intervals for an id whose payload matches, reading them from the file as
+    // they are needed
+    pub fn intervals<'a>(
+        &'a self, id: Id, payload: &'a PayloadPredicate
+    ) -> Result<impl Iterator<Item = Interval> + 'a> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.iter_intervals(base_offset, length, payload))
     }
 
-    fn is_contained(
-        &self, id: Id, target: Value, payload_mask: Payload, payload_value: Payload,
-        use_default: bool,
-        search_window: Value    // Window to search to the left since the list is possibly
-                                // overlapping. Set this to max interval len, ideally.
-    ) -> PyResult<bool> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                let interval_payload_size = INTERVAL_SIZE + self._impl.payload_len;
-
-                // Binary search to the index
-                let mut min_idx = 0;
-                let mut max_idx = *length;
-                while min_idx < max_idx {
-                    let pivot = (min_idx + max_idx) / 2;
-                    let pivot_int_and_p = self._impl.read_interval(
-                        base_offset + pivot * interval_payload_size);
-                    if pivot_int_and_p.0 <= target && pivot_int_and_p.1 > target {
-                        min_idx = pivot;
-                        max_idx = pivot;
-                    } else if pivot_int_and_p.0 > target {
-                        max_idx = pivot;
-                    } else {
-                        min_idx = pivot + 1;
-                    }
-                }
+    // Iterate over the intervals for an id whose payload matches and that overlap [start, end)
+    pub fn intervals_in<'a>(
+        &'a self, id: Id, start: Value, end: Value, payload: &'a PayloadPredicate
+    ) -> Result<impl Iterator<Item = Interval> + 'a> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let (lo, hi) = if end > start {
+            self.overlap_range(id, base_offset, length, start, end)
+        } else {
+            (0, 0)
+        };
+        let interval_payload_size = INTERVAL_SIZE + self.payload_len;
+        Ok(self.iter_intervals(base_offset + lo * interval_payload_size, hi - lo, payload)
+            .filter(move |int| int.1 > start))
+    }
 
-                // Look to the right
-                let mut i = min_idx;
-                while i < *length {
-                    let int_and_p = self._impl.read_interval(
-                        base_offset + i * interval_payload_size);
-                    if int_and_p.0 > target {
-                        break;
-                    }
-                    if int_and_p.0 <= target && int_and_p.1 > target
-                        && (payload_mask & int_and_p.2) == payload_value {
-                        return Ok(true);
-                    }
-                    i += 1;
-                }
+    // Iterate over the intervals for an id, along with their payloads
+    pub fn intervals_with_payload(
+        &self, id: Id
+    ) -> Result<impl Iterator<Item = IntervalAndPayload> + '_> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.iter_intervals_with_payload(base_offset, length))
+    }
 
-                // Look window to the left
-                i = min_idx;
-                while i > 0 {
-                    i -= 1;
-                    let int_and_p = self._impl.read_interval(
-                        base_offset + i * interval_payload_size);
-                    if int_and_p.0 + search_window < target {
-                        break;
-                    }
-                    if int_and_p.0 <= target && int_and_p.1 > target
-                        && (payload_mask & int_and_p.2) == payload_value {
-                        return Ok(true);
-                    }
-                }
+    // Get the intervals for an id as they are stored: a little endian u32 start and end,
+    // followed by payload_len bytes of little endian payload
+    pub fn get_interval_bytes(&self, id: Id) -> Result<&[u8]> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(&self.data.bytes(base_offset)[..length * (INTERVAL_SIZE + self.payload_len)])
+    }
 
-                Ok(false)
-            },
-            None => if use_default { Ok(false) } else {
-                Err(exceptions::IndexError::py_err("id not found"))
+    // Get the intervals for an id whose payload and length match
+    pub fn get_intervals(
+        &self, id: Id, payload: &PayloadPredicate, lengths: LengthFilter
+    ) -> Result<Vec<Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.iter_intervals(base_offset, length, payload).filter(
+            |&int| lengths.matches(int)
+        ).collect())
+    }
+
+    pub fn get_intervals_with_payload(&self, id: Id) -> Result<Vec<IntervalAndPayload>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.iter_intervals_with_payload(base_offset, length).collect())
+    }
+
+    // Read the intervals whose payload matches and that can overlap the span of a sorted list
+    // of intervals. Only the first window on an id reads all of its intervals (see
+    // overlap_range).
+    fn read_window(
+        &self, id: Id, (base_offset, length): (usize, usize), intervals: &[Interval],
+        payload: &PayloadPredicate
+    ) -> Vec<Interval> {
+        match span(intervals) {
+            Some((start, end)) if end > start => {
+                let (lo, hi) = self.overlap_range(id, base_offset, length, start, end);
+                let interval_payload_size = INTERVAL_SIZE + self.payload_len;
+                self.read_intervals(
+                    base_offset + lo * interval_payload_size, hi - lo, payload)
             },
+            _ => vec![]
         }
     }
 
-    fn intersect(
-        &self, id: Id, intervals: Vec<Interval>, payload_mask: Payload, payload_value: Payload,
-        use_default: bool
-    ) -> PyResult<Vec<Interval>> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                let mut res: Vec<Interval> = Vec::new();
-                let self_intervals = self._impl.read_intervals(
-                    *base_offset, *length, payload_mask, payload_value);
-                let mut i = 0;
-                let mut j = 0;
-                while i < intervals.len() && j < self_intervals.len() {
-                    let a = intervals[i];
-                    let b = self_intervals[j];
-                    let end = min(a.1, b.1);
-                    let start = max(a.0, b.0);
-                    if end > start {
-                        if res.len() > 0 {
-                            let res_len = res.len();
-                            let last_res = res[res_len - 1];
-                            if min(end, last_res.1) > max(start, last_res.0) {
-                                res[res_len - 1] = (min(start, last_res.0), max(end, last_res.1))
-                            } else {
-                                res.push((start, end));
-                            }
-                        } else {
-                            res.push((start, end));
-                        }
-                    }
-                    if a.1 <= b.1 {
-                        i += 1;
-                    } else {
-                        j += 1;
-                    }
-                }
-                Ok(res)
-            },
-            None => if use_default {
-                Ok(vec![])
-            } else {
-                Err(exceptions::IndexError::py_err("id not found"))
-            }
+    // The intervals that overlap [start, end) and whose payload matches, in the order they are
+    // stored
+    fn overlapping_offsets(
+        &self, id: Id, (base_offset, length): (usize, usize), start: Value, end: Value,
+        payload: &PayloadPredicate
+    ) -> Vec<IntervalAndPayload> {
+        if end <= start {
+            return vec![];
         }
+        let interval_payload_size = INTERVAL_SIZE + self.payload_len;
+        let (lo, hi) = self.overlap_range(id, base_offset, length, start, end);
+        (lo..hi).map(
+            |i| self.read_interval(base_offset + i * interval_payload_size)
+        ).filter(
+            |int_and_p| int_and_p.1 > start && payload.matches(int_and_p.2)
+        ).collect()
     }
 
-    fn intersect_sum(
-        &self, id: Id, intervals: Vec<Interval>, payload_mask: Payload, payload_value: Payload,
-        use_default: bool
-    ) -> PyResult<u64> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                let mut res: u64 = 0;
-                let self_intervals = self._impl.read_intervals(
-                    *base_offset, *length, payload_mask, payload_value);
-                let self_intervals_len = self_intervals.len();
-                let mut j_bound = 0;
-                for i in 0..intervals.len() {
-                    let a = intervals[i];
-                    let mut j_bound_end = 0;
-                    for j_local in j_bound..self_intervals_len {
-                        let b = self_intervals[j_local];
-                        let end = min(a.1, b.1);
-                        let start = max(a.0, b.0);
-                        if end > start {
-                            res += (end - start) as u64;
-                        }
-                        if a.1 <= b.0 {
-                            // a before b
-                            break;
-                        }
-                        j_bound_end = max(b.1, j_bound_end);
-                        if j_bound_end <= a.1 {
-                            j_bound = j_local;
-                        }
-                    }
-                    if j_bound == self_intervals_len {
-                        break;
+    fn stab_offsets(
+        &self, id: Id, offsets: (usize, usize), target: Value, payload: &PayloadPredicate
+    ) -> Vec<IntervalAndPayload> {
+        if target == Value::MAX {
+            return vec![];
+        }
+        self.overlapping_offsets(id, offsets, target, target + 1, payload)
+    }
+
+    // Get the intervals, with their payloads, that contain target and whose payload matches
+    pub fn stab(
+        &self, id: Id, target: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<IntervalAndPayload>> {
+        let offsets = self.get_offsets(id)?;
+        Ok(self.stab_offsets(id, offsets, target, payload))
+    }
+
+    // Same as stab, for each of several targets, which do not need to be sorted
+    pub fn stab_many(
+        &self, id: Id, targets: &[Value], payload: &PayloadPredicate
+    ) -> Result<Vec<Vec<IntervalAndPayload>>> {
+        let offsets = self.get_offsets(id)?;
+        Ok(targets.iter().map(
+            |&t| self.stab_offsets(id, offsets, t, payload)
+        ).collect())
+    }
+
+    // Get the intervals, with their payloads, that overlap [start, end) and whose payload
+    // matches. They are not clipped to the window.
+    pub fn overlapping(
+        &self, id: Id, start: Value, end: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<IntervalAndPayload>> {
+        let offsets = self.get_offsets(id)?;
+        Ok(self.overlapping_offsets(id, offsets, start, end, payload))
+    }
+
+    // Get the number of intervals whose payload matches that cover each point, as a step
+    // function of (start, end, count) segments. Points that are not covered are left out.
+    pub fn depth_profile(
+        &self, id: Id, payload: &PayloadPredicate
+    ) -> Result<Vec<(Value, Value, usize)>> {
+        Ok(ops::depth(self.intervals(id, payload)?).collect())
+    }
+
+    // Get the parts that are covered by at least min_depth intervals whose payload matches, as
+    // a set of intervals
+    pub fn min_depth(
+        &self, id: Id, min_depth: usize, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(ops::min_depth(self.intervals(id, payload)?, min_depth).collect())
+    }
+
+    // Get whether target is in any interval whose payload matches
+    pub fn is_contained(
+        &self, id: Id, target: Value, payload: &PayloadPredicate
+    ) -> Result<bool> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        if target == Value::MAX {
+            return Ok(false);
+        }
+        let interval_payload_size = INTERVAL_SIZE + self.payload_len;
+        let (lo, hi) = self.overlap_range(id, base_offset, length, target, target + 1);
+        Ok((lo..hi).any(|i| {
+            let int_and_p = self.read_interval(base_offset + i * interval_payload_size);
+            int_and_p.1 > target && payload.matches(int_and_p.2)
+        }))
+    }
+
+    // Get the parts of [lo, hi) that are not in any interval for an id whose payload matches
+    pub fn complement(
+        &self, id: Id, lo: Value, hi: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(self.iter_complement(id, lo, hi, payload)?.collect())
+    }
+
+    // Complement and then sum
+    pub fn complement_sum(
+        &self, id: Id, lo: Value, hi: Value, payload: &PayloadPredicate
+    ) -> Result<u64> {
+        Ok(self.iter_complement(id, lo, hi, payload)?.fold(
+            0u64, |acc, int| acc + (int.1 - int.0) as u64))
+    }
+
+    fn iter_complement(
+        &self, id: Id, lo: Value, hi: Value, payload: &PayloadPredicate
+    ) -> Result<impl Iterator<Item = Interval>> {
+        let offsets = self.get_offsets(id)?;
+        let bounds = if hi > lo { Some((lo, hi)) } else { None };
+        let window = self.read_window(id, offsets, &[(lo, hi)], payload);
+        Ok(ops::minus(bounds.into_iter(), ops::coalesce(window.into_iter(), 0)))
+    }
+
+    // Extend the intervals whose payload matches by amount at both ends, merging overlaps
+    pub fn dilate(
+        &self, id: Id, amount: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(ops::dilate(self.intervals(id, payload)?, amount).collect())
+    }
+
+    // Shrink the intervals whose payload matches by amount at both ends, dropping empty ones
+    pub fn erode(
+        &self, id: Id, amount: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(ops::erode(self.intervals(id, payload)?, amount).collect())
+    }
+
+    // Merge the intervals whose payload matches that are less than gap apart
+    pub fn coalesce(
+        &self, id: Id, gap: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(ops::coalesce(self.intervals(id, payload)?, gap).collect())
+    }
+
+    // Remove the parts of the matching intervals that are at most 2 * amount long
+    pub fn opening(
+        &self, id: Id, amount: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(ops::opening(self.intervals(id, payload)?, amount).collect())
+    }
+
+    // Fill the gaps between the matching intervals that are at most 2 * amount long
+    pub fn closing(
+        &self, id: Id, amount: Value, payload: &PayloadPredicate
+    ) -> Result<Vec<Interval>> {
+        Ok(ops::closing(self.intervals(id, payload)?, amount).collect())
+    }
+
+    // Intersect a sorted list of intervals with the intervals whose payload and length match
+    pub fn intersect(
+        &self, id: Id, intervals: &[Interval], payload: &PayloadPredicate,
+        lengths: LengthFilter
+    ) -> Result<Vec<Interval>> {
+        let offsets = self.get_offsets(id)?;
+        let mut res: Vec<Interval> = Vec::new();
+        let mut self_intervals = self.read_window(
+            id, offsets, intervals, payload);
+        self_intervals.retain(|&int| lengths.matches(int));
+        let mut i = 0;
+        let mut j = 0;
+        while i < intervals.len() && j < self_intervals.len() {
+            let a = intervals[i];
+            let b = self_intervals[j];
+            let end = min(a.1, b.1);
+            let start = max(a.0, b.0);
+            if end > start {
+                if !res.is_empty() {
+                    let res_len = res.len();
+                    let last_res = res[res_len - 1];
+                    if min(end, last_res.1) > max(start, last_res.0) {
+                        res[res_len - 1] = (min(start, last_res.0), max(end, last_res.1))
+                    } else {
+                        res.push((start, end));
                     }
+                } else {
+                    res.push((start, end));
                 }
-                Ok(res)
-            },
-            None => if use_default {
-                Ok(0)
+            }
+            if a.1 <= b.1 {
+                i += 1;
             } else {
-                Err(exceptions::IndexError::py_err("id not found"))
+                j += 1;
             }
         }
+        Ok(res)
     }
 
-    #[new]
-    unsafe fn new(data_file: String, payload_len: usize) -> PyResult<Self> {
-        match File::open(&data_file) {
-            Ok(data_fh) => {
-                let metadata = File::metadata(&data_fh)?;
-                let length = metadata.len() as usize;
-
-                // Empty file case
-                if length == 0 {
-                    return Ok(MmapIntervalListMapping {
-                        _impl: _MmapIntervalListMapping {
-                            data: None, offsets: BTreeMap::new(), payload_len: payload_len
-                        }
-                    });
+    // Intersect and then sum. Overlapping intervals are counted as many times as they overlap.
+    pub fn intersect_sum(
+        &self, id: Id, intervals: &[Interval], payload: &PayloadPredicate,
+        lengths: LengthFilter
+    ) -> Result<u64> {
+        let offsets = self.get_offsets(id)?;
+        let mut res: u64 = 0;
+        let mut self_intervals = self.read_window(
+            id, offsets, intervals, payload);
+        self_intervals.retain(|&int| lengths.matches(int));
+        let self_intervals_len = self_intervals.len();
+        let mut j_bound = 0;
+        for a in intervals {
+            let mut j_bound_end = 0;
+            let j_start = j_bound;
+            for (j_local, b) in self_intervals.iter().enumerate().skip(j_start) {
+                let end = min(a.1, b.1);
+                let start = max(a.0, b.0);
+                if end > start {
+                    res += (end - start) as u64;
                 }
-
-                let mmap = MmapOptions::new().map(&data_fh);
-                match mmap {
-                    Ok(m) => match parse_offsets(&m, payload_len) {
-                        Some(offsets) => {
-                            Ok(MmapIntervalListMapping {
-                                _impl: _MmapIntervalListMapping {
-                                    data: Some(m), offsets: offsets, payload_len: payload_len
-                                }
-                            })
-                        },
-                        None => Err(exceptions::Exception::py_err("cannot parse offsets"))
-                    },
-                    Err(s) => Err(exceptions::Exception::py_err(s.to_string()))
+                if a.1 <= b.0 {
+                    // a before b
+                    break;
                 }
-            },
-            Err(s) => Err(exceptions::Exception::py_err(s.to_string()))
+                j_bound_end = max(b.1, j_bound_end);
+                if j_bound_end <= a.1 {
+                    j_bound = j_local;
+                }
+            }
+            if j_bound == self_intervals_len {
+                break;
+            }
         }
+        Ok(res)
     }
 }
@@ -5,26 +5,41 @@
 * and are defined as [start, end). The intervals are sorted by start. Intervals have no payload.
 */
 
-use pyo3::prelude::*;
-use pyo3::exceptions;
 use std::cmp::{max, min};
-use std::collections::BTreeMap;
 use std::mem;
-use std::fs::File;
-use memmap::{MmapOptions, Mmap};
 
 use common::*;
+use error::{Error, Result};
+use ops;
 
-struct _MmapIntervalSetMapping {
-    data: Option<Mmap>,
-    offsets: BTreeMap<Id, (usize, usize)>,
+pub struct MmapIntervalSetMapping {
+    data: Storage,
+    offsets: IdOffsets,
 }
 
-impl _MmapIntervalSetMapping {
+impl MmapIntervalSetMapping {
+
+    // In strict mode, check that the intervals for every id are sorted and non-overlapping
+    pub fn open(path: &str, strict: bool, duplicates: Duplicates) -> Result<Self> {
+        let (data, offsets, _) = open_mapping(path, Kind::Set, Some(0), duplicates)?;
+        let mapping = MmapIntervalSetMapping {data, offsets};
+        if strict {
+            mapping.check_intervals()?;
+        }
+        Ok(mapping)
+    }
+
+    fn get_offsets(&self, id: Id) -> Result<(usize, usize)> {
+        self.offsets.get(self.data.mmap(), id).ok_or(Error::IdNotFound(id))
+    }
+
+    fn iter_offsets(&self) -> Box<dyn Iterator<Item = (Id, (usize, usize))> + '_> {
+        self.offsets.iter(self.data.mmap())
+    }
 
     fn read_interval(&self, i: usize) -> Interval {
-        let m = &self.data.as_ref().unwrap();
-        (mmap_read_u32(m, i), mmap_read_u32(m, i + mem::size_of::<u32>()))
+        let m = self.data.bytes(i);
+        (mmap_read_u32(m, 0), mmap_read_u32(m, mem::size_of::<u32>()))
     }
 
     fn binary_search(&self, base_offset: usize, n: usize, target: Value,
@@ -34,7 +49,7 @@ impl _MmapIntervalSetMapping {
 
         while max_idx > min_idx {
             let pivot = (min_idx + max_idx) / 2;
-            let pivot_int = self.read_interval(base_offset + (pivot as usize) * INTERVAL_SIZE);
+            let pivot_int = self.read_interval(base_offset + pivot * INTERVAL_SIZE);
             if target >= pivot_int.0 && target < pivot_int.1 {
                 return Some(pivot);
             } else if target < pivot_int.0 {
@@ -50,297 +65,354 @@ impl _MmapIntervalSetMapping {
         }
     }
 
+    // The index of the first interval for which pred holds, given that it holds for all the
+    // intervals after it
+    fn first_index<F>(&self, base_offset: usize, length: usize, pred: F) -> usize
+        where F: Fn(Interval) -> bool
+    {
+        let mut min_idx = 0;
+        let mut max_idx = length;
+        while min_idx < max_idx {
+            let pivot = (min_idx + max_idx) / 2;
+            if pred(self.read_interval(base_offset + pivot * INTERVAL_SIZE)) {
+                max_idx = pivot;
+            } else {
+                min_idx = pivot + 1;
+            }
+        }
+        min_idx
+    }
+
+    // Read the intervals that can overlap the span of a sorted list of intervals. Since the
+    // intervals in a set are sorted and do not overlap, their ends are sorted too.
+    fn read_window(
+        &self, base_offset: usize, length: usize, intervals: &[Interval]
+    ) -> Vec<Interval> {
+        match span(intervals) {
+            Some((start, end)) => {
+                let lo = self.first_index(base_offset, length, |int| int.1 > start);
+                let hi = self.first_index(base_offset, length, |int| int.0 >= end);
+                self.read_intervals(base_offset + lo * INTERVAL_SIZE, hi.saturating_sub(lo))
+            },
+            None => vec![]
+        }
+    }
+
     fn read_intervals(&self, base_offset: usize, length: usize) -> Vec<Interval> {
-        (0..length).map(
-            |i| self.read_interval(base_offset + i * INTERVAL_SIZE)
-        ).collect()
+        self.iter_intervals(base_offset, length).collect()
     }
 
-}
+    fn iter_intervals(
+        &self, base_offset: usize, length: usize
+    ) -> impl Iterator<Item = Interval> + '_ {
+        (0..length).map(move |i| self.read_interval(base_offset + i * INTERVAL_SIZE))
+    }
 
-#[pyclass]
-pub struct MmapIntervalSetMapping {
-    _impl: _MmapIntervalSetMapping,
-}
+    fn check_intervals(&self) -> Result<()> {
+        for (id, (base_offset, length)) in self.iter_offsets() {
+            if let Some((i, msg)) = find_set_violation(self.iter_intervals(base_offset, length)) {
+                return Err(Error::Format(format!("id {} interval {}: {}", id, i, msg)));
+            }
+        }
+        Ok(())
+    }
 
-#[pymethods]
-impl MmapIntervalSetMapping {
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
 
-    fn len(&self) -> PyResult<usize> {
-        Ok(self._impl.offsets.len())
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    fn get_ids(&self) -> PyResult<Vec<Id>> {
-        Ok(self._impl.offsets.keys().map(|k| *k).collect())
+    // Iterate over the ids in ascending order
+    pub fn ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.iter_offsets().map(|(k, _)| k)
     }
 
-    fn has_id(&self, id: Id) -> PyResult<bool> {
-        Ok(self._impl.offsets.contains_key(&id))
+    pub fn has_id(&self, id: Id) -> bool {
+        self.get_offsets(id).is_ok()
     }
 
-    fn sum(&self) -> PyResult<u64> {
-        Ok(self._impl.offsets.iter().fold(
+    // Sum the lengths of the intervals whose length matches
+    pub fn sum(&self, lengths: LengthFilter) -> u64 {
+        self.iter_offsets().fold(
             0u64, |total, (_, (base_offset, length))| {
-                total + self._impl.read_intervals(*base_offset, *length).iter().fold(
+                total + self.iter_intervals(base_offset, length).filter(
+                    |&int| lengths.matches(int)
+                ).fold(
                     0u64, |acc, int| acc + (int.1 - int.0) as u64
                 )
             }
-        ))
+        )
     }
 
     // Get the number of intervals for an id
-    fn get_interval_count(&self, id: Id) -> PyResult<usize> {
-        match self._impl.offsets.get(&id) {
-            Some((_, length)) => {
-                Ok(*length)
-            },
-            None => Err(exceptions::IndexError::py_err("id not found")),
-        }
+    pub fn get_interval_count(&self, id: Id) -> Result<usize> {
+        Ok(self.get_offsets(id)?.1)
     }
 
     // Get an interval by index
-    fn get_interval(&self, id: Id, idx: usize) -> PyResult<Interval> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                if idx >= *length {
-                    return Err(exceptions::IndexError::py_err("index out of range"));
-                }
-                Ok(self._impl.read_interval(*base_offset + idx * INTERVAL_SIZE))
-            },
-            None => Err(exceptions::IndexError::py_err("id not found")),
+    pub fn get_interval(&self, id: Id, idx: usize) -> Result<Interval> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        if idx >= length {
+            return Err(Error::IndexOutOfRange(idx));
         }
+        Ok(self.read_interval(base_offset + idx * INTERVAL_SIZE))
     }
 
-    // Get all intervals for an id
-    fn get_intervals(&self, id: Id, use_default: bool) -> PyResult<Vec<Interval>> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => Ok(self._impl.read_intervals(*base_offset, *length)),
-            None => if use_default {
-                Ok(vec![])
-            } else {
-                Err(exceptions::IndexError::py_err("id not found"))
-            }
-        }
+    // Iterate over the intervals for an id, reading them from the file as they are needed
+    pub fn intervals(&self, id: Id) -> Result<impl Iterator<Item = Interval> + '_> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.iter_intervals(base_offset, length))
+    }
+
+    // Iterate over the intervals for an id that overlap [start, end)
+    pub fn intervals_in(
+        &self, id: Id, start: Value, end: Value
+    ) -> Result<impl Iterator<Item = Interval> + '_> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let lo = self.first_index(base_offset, length, |int| int.1 > start);
+        let hi = self.first_index(base_offset, length, |int| int.0 >= end);
+        Ok(self.iter_intervals(base_offset + lo * INTERVAL_SIZE, hi.saturating_sub(lo)))
+    }
+
+    // Get the intervals for an id as they are stored: pairs of little endian u32s
+    pub fn get_interval_bytes(&self, id: Id) -> Result<&[u8]> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(&self.data.bytes(base_offset)[..length * INTERVAL_SIZE])
+    }
+
+    // Get all intervals for an id whose length matches
+    pub fn get_intervals(&self, id: Id, lengths: LengthFilter) -> Result<Vec<Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.iter_intervals(base_offset, length).filter(|&int| lengths.matches(int)).collect())
     }
 
     // Get whether a target is in any of the intervals in the set
-    fn is_contained(&self, id: Id, target: Value, use_default: bool) -> PyResult<bool> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                Ok(self._impl.binary_search(*base_offset, *length, target, false).is_some())
+    pub fn is_contained(&self, id: Id, target: Value) -> Result<bool> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(self.binary_search(base_offset, length, target, false).is_some())
+    }
+
+    // Get whether start and end intersect with any interval in the set
+    pub fn has_intersection(&self, id: Id, start: Value, end: Value) -> Result<bool> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(match self.binary_search(base_offset, length, start, true) {
+            Some(min_idx) => {
+                let mut isects = false;
+                let mut i = min_idx;
+                while i < length {
+                    let curr_int = self.read_interval(base_offset + i * INTERVAL_SIZE);
+                    if min(end, curr_int.1) > max(start, curr_int.0) {
+                        isects = true;
+                        break;
+                    }
+                    if curr_int.0 > end {
+                        break;
+                    }
+                    i += 1;
+                }
+                isects
             },
-            None => if use_default {
-                Ok(false)
+            None => false
+        })
+    }
+
+    // Intersect a sorted list of intervals with the intervals whose length matches
+    pub fn intersect(
+        &self, id: Id, intervals: &[Interval], lengths: LengthFilter
+    ) -> Result<Vec<Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let mut res = Vec::new();
+        let mut self_intervals = self.read_window(base_offset, length, intervals);
+        self_intervals.retain(|&int| lengths.matches(int));
+        let mut i = 0;
+        let mut j = 0;
+        while i < intervals.len() && j < self_intervals.len() {
+            let a = intervals[i];
+            let b = self_intervals[j];
+            let end = min(a.1, b.1);
+            let start = max(a.0, b.0);
+            if end > start {
+                res.push((start, end));
+            }
+            if a.1 <= b.1 {
+                i += 1;
             } else {
-                Err(exceptions::IndexError::py_err("id not found"))
+                j += 1;
             }
         }
+        Ok(res)
     }
 
-    // Get whether start and end intersect with any interval in the set
-    fn has_intersection(&self, id: Id, start: Value, end: Value,
-                            use_default: bool) -> PyResult<bool> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => Ok(
-                match self._impl.binary_search(*base_offset, *length, start, true) {
-                    Some(min_idx) => {
-                        let mut isects = false;
-                        let mut i = min_idx;
-                        while i < *length {
-                            let curr_int = self._impl.read_interval(
-                                *base_offset + i * INTERVAL_SIZE);
-                            if min(end, curr_int.1) > max(start, curr_int.0) {
-                                isects = true;
-                                break;
-                            }
-                            if curr_int.0 > end {
-                                break;
-                            }
-                            i += 1;
-                        }
-                        isects
-                    },
-                    None => false
-                }
-            ),
-            None => if use_default {
-                Ok(false)
+    // Intersect and then sum
+    pub fn intersect_sum(
+        &self, id: Id, intervals: &[Interval], lengths: LengthFilter
+    ) -> Result<usize> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let mut sum = 0usize;
+        let mut self_intervals = self.read_window(base_offset, length, intervals);
+        self_intervals.retain(|&int| lengths.matches(int));
+        let mut i = 0;
+        let mut j = 0;
+        while i < intervals.len() && j < self_intervals.len() {
+            let a = intervals[i];
+            let b = self_intervals[j];
+            let end = min(a.1, b.1);
+            let start = max(a.0, b.0);
+            if end > start {
+                sum += (end - start) as usize;
+            }
+            if a.1 <= b.1 {
+                i += 1;
             } else {
-                Err(exceptions::IndexError::py_err("id not found"))
+                j += 1;
             }
         }
+        Ok(sum)
     }
 
-    // Intersect a sorted list of intervals
-    fn intersect(&self, id: Id, intervals: Vec<Interval>,
-                 use_default: bool) -> PyResult<Vec<Interval>> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                let mut res = Vec::new();
-                let self_intervals = self._impl.read_intervals(*base_offset, *length);
-                let mut i = 0;
-                let mut j = 0;
-                while i < intervals.len() && j < self_intervals.len() {
-                    let a = intervals[i];
-                    let b = self_intervals[j];
-                    let end = min(a.1, b.1);
-                    let start = max(a.0, b.0);
-                    if end > start {
-                        res.push((start, end));
-                    }
+    // Minus this from intervals
+    pub fn minus(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let mut res = Vec::new();
+        let self_intervals = self.read_window(base_offset, length, intervals);
+        let mut i = 0;
+        let mut j = 0;
+        let mut mod_a: Option<Interval> = None;
+        while i < intervals.len() && j < self_intervals.len() {
+            if mod_a.is_none() {
+                mod_a = Some(intervals[i])
+            }
+            let a = mod_a.unwrap();
+            let b = self_intervals[j];
+            if a.0 < b.0 {
+                if a.1 <= b.0 {
+                    // a before b
+                    res.push(a);
+                    mod_a = None;
+                    i += 1;
+                } else {
+                    // a's tail overlaps
+                    res.push((a.0, b.0));
                     if a.1 <= b.1 {
+                        // rest of a in b
+                        mod_a = None;
                         i += 1;
                     } else {
+                        // some of a is left
+                        mod_a = Some((b.1, a.1));
                         j += 1;
                     }
                 }
-                Ok(res)
-            },
-            None => if use_default {
-                Ok(vec![])
             } else {
-                Err(exceptions::IndexError::py_err("id not found"))
-            }
-        }
-    }
-
-    // Intersect and then sum
-    fn intersect_sum(&self, id: Id, intervals: Vec<Interval>,
-                 use_default: bool) -> PyResult<usize> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                let mut sum = 0usize;
-                let self_intervals = self._impl.read_intervals(*base_offset, *length);
-                let mut i = 0;
-                let mut j = 0;
-                while i < intervals.len() && j < self_intervals.len() {
-                    let a = intervals[i];
-                    let b = self_intervals[j];
-                    let end = min(a.1, b.1);
-                    let start = max(a.0, b.0);
-                    if end > start {
-                        sum += (end - start) as usize;
-                    }
+                if a.0 >= b.1 {
+                    // b before a
+                    j += 1;
+                } else {
                     if a.1 <= b.1 {
+                        // a in b
+                        mod_a = None;
                         i += 1;
                     } else {
+                        // some of a is left
+                        mod_a = Some((b.1, a.1));
                         j += 1;
                     }
                 }
-                Ok(sum)
-            },
-            None => if use_default {
-                Ok(0)
-            } else {
-                Err(exceptions::IndexError::py_err("id not found"))
             }
         }
+        if let Some(a) = mod_a {
+            res.push(a);
+            i += 1;
+        }
+        while i < intervals.len() {
+            res.push(intervals[i]);
+            i += 1;
+        }
+        Ok(res)
     }
 
-    // Minus this from intervals
-    fn minus(&self, id: Id, intervals: Vec<Interval>, use_default: bool) -> PyResult<Vec<Interval>> {
-        match self._impl.offsets.get(&id) {
-            Some((base_offset, length)) => {
-                let mut res = Vec::new();
-                let self_intervals = self._impl.read_intervals(*base_offset, *length);
-                let mut i = 0;
-                let mut j = 0;
-                let mut mod_a: Option<Interval> = None;
-                while i < intervals.len() && j < self_intervals.len() {
-                    if mod_a.is_none() {
-                        mod_a = Some(intervals[i])
-                    }
-                    let a = mod_a.unwrap();
-                    let b = self_intervals[j];
-                    if a.0 < b.0 {
-                        if a.1 <= b.0 {
-                            // a before b
-                            res.push(a);
-                            mod_a = None;
-                            i += 1;
-                        } else {
-                            // a's tail overlaps
-                            res.push((a.0, b.0));
-                            if a.1 <= b.1 {
-                                // rest of a in b
-                                mod_a = None;
-                                i += 1;
-                            } else {
-                                // some of a is left
-                                mod_a = Some((b.1, a.1));
-                                j += 1;
-                            }
-                        }
-                    } else {
-                        if a.0 >= b.1 {
-                            // b before a
-                            j += 1;
-                        } else {
-                            if a.1 <= b.1 {
-                                // a in b
-                                mod_a = None;
-                                i += 1;
-                            } else {
-                                // some of a is left
-                                mod_a = Some((b.1, a.1));
-                                j += 1;
-                            }
-                        }
-                    }
-                }
-                if mod_a.is_some() {
-                    res.push(mod_a.unwrap());
-                    i += 1;
-                }
-                while i < intervals.len() {
-                    res.push(intervals[i]);
-                    i += 1;
-                }
-                Ok(res)
-            },
-            None => if use_default {
-                Ok(intervals)
-            } else {
-                Err(exceptions::IndexError::py_err("id not found"))
-            }
-        }
+    // Merge a sorted list of intervals into this
+    pub fn union(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        Ok(self.iter_union(id, intervals)?.collect())
     }
 
-    #[new]
-    unsafe fn new(data_file: String) -> PyResult<Self> {
-        match File::open(&data_file) {
-            Ok(data_fh) => {
-                let metadata = File::metadata(&data_fh)?;
-                let length = metadata.len() as usize;
+    // Merge and then sum
+    pub fn union_sum(&self, id: Id, intervals: &[Interval]) -> Result<u64> {
+        Ok(self.iter_union(id, intervals)?.fold(0u64, |acc, int| acc + (int.1 - int.0) as u64))
+    }
 
-                // Empty file case
-                if length == 0 {
-                    return Ok(MmapIntervalSetMapping {
-                        _impl: _MmapIntervalSetMapping {data: None, offsets: BTreeMap::new()}
-                    });
-                }
+    fn iter_union<'a>(
+        &'a self, id: Id, intervals: &'a [Interval]
+    ) -> Result<impl Iterator<Item = Interval> + 'a> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let iters: Vec<Box<dyn Iterator<Item = Interval>>> = vec![
+            Box::new(self.iter_intervals(base_offset, length)),
+            Box::new(intervals.iter().cloned())
+        ];
+        Ok(ops::coalesce(ops::merge(iters), 0))
+    }
 
-                if length % mem::size_of::<u32>() != 0 {
-                    return Err(exceptions::Exception::py_err(
-                               "file length is not a multiple of 4"))
-                }
+    // Get the parts of this and of a sorted list of intervals that are not in both
+    pub fn symmetric_difference(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(ops::symmetric_difference(
+            self.iter_intervals(base_offset, length),
+            ops::coalesce(intervals.iter().cloned(), 0)
+        ).collect())
+    }
 
-                let mmap = MmapOptions::new().map(&data_fh);
-                match mmap {
-                    Ok(m) => match parse_offsets(&m, 0) {
-                        Some(offsets) => {
-                            Ok(MmapIntervalSetMapping {
-                                _impl: _MmapIntervalSetMapping {
-                                    data: Some(m), offsets: offsets
-                                }
-                            })
-                        },
-                        None => Err(exceptions::Exception::py_err("cannot parse offsets"))
-                    },
-                    Err(s) => Err(exceptions::Exception::py_err(s.to_string()))
-                }
-            },
-            Err(s) => Err(exceptions::Exception::py_err(s.to_string()))
-        }
+    // Minus a sorted list of intervals from this, the converse of minus
+    pub fn difference_from(&self, id: Id, intervals: &[Interval]) -> Result<Vec<Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        Ok(ops::minus(
+            self.iter_intervals(base_offset, length), ops::coalesce(intervals.iter().cloned(), 0)
+        ).collect())
+    }
+
+    // Get the parts of [lo, hi) that are not in any interval for an id
+    pub fn complement(&self, id: Id, lo: Value, hi: Value) -> Result<Vec<Interval>> {
+        Ok(self.iter_complement(id, lo, hi)?.collect())
+    }
+
+    // Complement and then sum
+    pub fn complement_sum(&self, id: Id, lo: Value, hi: Value) -> Result<u64> {
+        Ok(self.iter_complement(id, lo, hi)?.fold(0u64, |acc, int| acc + (int.1 - int.0) as u64))
+    }
+
+    fn iter_complement(
+        &self, id: Id, lo: Value, hi: Value
+    ) -> Result<impl Iterator<Item = Interval>> {
+        let (base_offset, length) = self.get_offsets(id)?;
+        let bounds = if hi > lo { Some((lo, hi)) } else { None };
+        let window = self.read_window(base_offset, length, &[(lo, hi)]);
+        Ok(ops::minus(bounds.into_iter(), window.into_iter()))
+    }
+
+    // Extend each interval by amount at both ends, merging those that then overlap
+    pub fn dilate(&self, id: Id, amount: Value) -> Result<Vec<Interval>> {
+        Ok(ops::dilate(self.intervals(id)?, amount).collect())
+    }
+
+    // Shrink each interval by amount at both ends, dropping those that vanish
+    pub fn erode(&self, id: Id, amount: Value) -> Result<Vec<Interval>> {
+        Ok(ops::erode(self.intervals(id)?, amount).collect())
+    }
+
+    // Merge intervals that are less than gap apart
+    pub fn coalesce(&self, id: Id, gap: Value) -> Result<Vec<Interval>> {
+        Ok(ops::coalesce(self.intervals(id)?, gap).collect())
+    }
+
+    // Remove the parts of intervals that are at most 2 * amount long
+    pub fn opening(&self, id: Id, amount: Value) -> Result<Vec<Interval>> {
+        Ok(ops::opening(self.intervals(id)?, amount).collect())
+    }
+
+    // Fill the gaps between intervals that are at most 2 * amount long
+    pub fn closing(&self, id: Id, amount: Value) -> Result<Vec<Interval>> {
+        Ok(ops::closing(self.intervals(id)?, amount).collect())
     }
 }
@@ -1,19 +1,31 @@
+#[cfg(feature = "python")]
 extern crate pyo3;
 extern crate memmap;
 extern crate byteorder;
 
 mod common;
-mod isetmap;
-mod ilistmap;
+pub mod error;
+pub mod isetmap;
+pub mod ilistmap;
+pub mod ops;
+pub mod payload;
+pub mod combine;
+pub mod expr;
+pub mod query;
+pub mod writer;
+pub mod verify;
 
-use pyo3::prelude::{PyModule, PyResult, pymodule};
-use pyo3::Python;
-use isetmap::MmapIntervalSetMapping;
-use ilistmap::MmapIntervalListMapping;
+#[cfg(feature = "python")]
+mod python;
 
-#[pymodule]
-fn rs_intervalset(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
-    m.add_class::<MmapIntervalSetMapping>()?;
-    m.add_class::<MmapIntervalListMapping>()?;
-    Ok(())
-}
+pub use common::{Id, Value, Interval, Payload, Kind, Duplicates, LengthFilter};
+pub use error::{Error, Result};
+pub use isetmap::MmapIntervalSetMapping;
+pub use payload::PayloadPredicate;
+pub use ilistmap::{IntervalAndPayload, MmapIntervalListMapping};
+pub use combine::{
+    CombinedMapping, ListUnionMapping, SetUnionMapping, SetIntersectionMapping
+};
+pub use expr::Expr;
+pub use query::{Binding, ParseError, Query};
+pub use writer::{IntervalSetMappingWriter, IntervalListMappingWriter};
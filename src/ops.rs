@@ -0,0 +1,377 @@
+/*
+* Streaming operations on sorted intervals
+*
+* Each operation is an iterator adapter that reads its inputs as they are needed, so they can
+* be chained over the iterators returned by the mappings without collecting intermediate
+* results. Inputs must be sorted by start.
+*/
+
+use std::cmp::{max, min, Reverse};
+use std::collections::BinaryHeap;
+use std::iter::Peekable;
+
+use common::*;
+
+// Merge any number of sorted streams into one sorted stream, keeping overlapping intervals
+pub fn merge<I: Iterator<Item = Interval>>(mut iters: Vec<I>) -> Merge<I> {
+    let mut heap = BinaryHeap::with_capacity(iters.len());
+    for (i, iter) in iters.iter_mut().enumerate() {
+        if let Some(int) = iter.next() {
+            heap.push(Reverse((int, i)));
+        }
+    }
+    Merge {iters, heap}
+}
+
+pub struct Merge<I> {
+    iters: Vec<I>,
+    heap: BinaryHeap<Reverse<(Interval, usize)>>,
+}
+
+impl<I: Iterator<Item = Interval>> Iterator for Merge<I> {
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        let Reverse((int, i)) = self.heap.pop()?;
+        if let Some(next) = self.iters[i].next() {
+            self.heap.push(Reverse((next, i)));
+        }
+        Some(int)
+    }
+}
+
+// Merge intervals that overlap, or that are less than fuzz apart. Intervals that only touch
+// are kept apart unless fuzz is non-zero.
+pub fn coalesce<I: Iterator<Item = Interval>>(iter: I, fuzz: Value) -> Coalesce<I> {
+    Coalesce {iter: iter.peekable(), fuzz}
+}
+
+pub struct Coalesce<I: Iterator> {
+    iter: Peekable<I>,
+    fuzz: Value,
+}
+
+impl<I: Iterator<Item = Interval>> Iterator for Coalesce<I> {
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        let (mut start, mut end) = self.iter.next()?;
+        while let Some(&(a, b)) = self.iter.peek() {
+            if min(end, b).saturating_add(self.fuzz) <= max(start, a) {
+                break;
+            }
+            start = min(start, a);
+            end = max(end, b);
+            self.iter.next();
+        }
+        Some((start, end))
+    }
+}
+
+// Intersect two sorted streams of non-overlapping intervals
+pub fn intersect<A, B>(a: A, b: B) -> Intersect<A, B>
+    where A: Iterator<Item = Interval>, B: Iterator<Item = Interval>
+{
+    Intersect {a: a.peekable(), b: b.peekable()}
+}
+
+pub struct Intersect<A: Iterator, B: Iterator> {
+    a: Peekable<A>,
+    b: Peekable<B>,
+}
+
+impl<A, B> Iterator for Intersect<A, B>
+    where A: Iterator<Item = Interval>, B: Iterator<Item = Interval>
+{
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        loop {
+            let x = *self.a.peek()?;
+            let y = *self.b.peek()?;
+            let start = max(x.0, y.0);
+            let end = min(x.1, y.1);
+            if x.1 <= y.1 {
+                self.a.next();
+            } else {
+                self.b.next();
+            }
+            if end > start {
+                return Some((start, end));
+            }
+        }
+    }
+}
+
+// Remove the intervals in b from those in a. Both must be sorted; b must be non-overlapping.
+pub fn minus<A, B>(a: A, b: B) -> Minus<A, B>
+    where A: Iterator<Item = Interval>, B: Iterator<Item = Interval>
+{
+    Minus {a, b: b.peekable(), rest: None}
+}
+
+pub struct Minus<A, B: Iterator> {
+    a: A,
+    b: Peekable<B>,
+    rest: Option<Interval>,     // What is left of the current interval in a
+}
+
+impl<A, B> Iterator for Minus<A, B>
+    where A: Iterator<Item = Interval>, B: Iterator<Item = Interval>
+{
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        'outer: loop {
+            let mut x = match self.rest.take() {
+                Some(x) => x,
+                None => self.a.next()?
+            };
+            while let Some(&y) = self.b.peek() {
+                if y.1 <= x.0 {
+                    // y before x
+                    self.b.next();
+                } else if y.0 >= x.1 {
+                    // x before y
+                    break;
+                } else if x.0 < y.0 {
+                    // x's head is left, and maybe its tail
+                    if x.1 > y.1 {
+                        self.rest = Some((y.1, x.1));
+                        self.b.next();
+                    }
+                    return Some((x.0, y.0));
+                } else if x.1 <= y.1 {
+                    // x in y
+                    continue 'outer;
+                } else {
+                    // x's tail is left
+                    x = (y.1, x.1);
+                    self.b.next();
+                }
+            }
+            return Some(x);
+        }
+    }
+}
+
+// The parts covered by exactly one of two sorted streams of non-overlapping intervals
+pub fn symmetric_difference<A, B>(a: A, b: B) -> SymmetricDifference<A, B>
+    where A: Iterator<Item = Interval>, B: Iterator<Item = Interval>
+{
+    SymmetricDifference {a, b, x: None, y: None}
+}
+
+pub struct SymmetricDifference<A, B> {
+    a: A,
+    b: B,
+    x: Option<Interval>,    // What is left of the current interval in a
+    y: Option<Interval>,    // What is left of the current interval in b
+}
+
+impl<A, B> Iterator for SymmetricDifference<A, B>
+    where A: Iterator<Item = Interval>, B: Iterator<Item = Interval>
+{
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        loop {
+            if self.x.is_none() {
+                self.x = self.a.next();
+            }
+            if self.y.is_none() {
+                self.y = self.b.next();
+            }
+            let (x, y) = match (self.x, self.y) {
+                (Some(x), Some(y)) => (x, y),
+                (None, _) => return self.y.take(),
+                (_, None) => return self.x.take(),
+            };
+            if x.1 <= y.0 {
+                // x before y
+                return self.x.take();
+            } else if y.1 <= x.0 {
+                // y before x
+                return self.y.take();
+            } else if x.0 < y.0 {
+                // x's head is only in a
+                self.x = Some((y.0, x.1));
+                return Some((x.0, y.0));
+            } else if y.0 < x.0 {
+                // y's head is only in b
+                self.y = Some((x.0, y.1));
+                return Some((y.0, x.0));
+            } else {
+                // Drop the part that is in both
+                let end = min(x.1, y.1);
+                self.x = if x.1 > end { Some((end, x.1)) } else { None };
+                self.y = if y.1 > end { Some((end, y.1)) } else { None };
+            }
+        }
+    }
+}
+
+// Intersect any number of sorted streams of non-overlapping intervals in one pass
+pub fn intersect_all<I: Iterator<Item = Interval>>(iters: Vec<I>) -> IntersectAll<I> {
+    IntersectAll {iters: iters.into_iter().map(Iterator::peekable).collect()}
+}
+
+pub struct IntersectAll<I: Iterator> {
+    iters: Vec<Peekable<I>>,
+}
+
+impl<I: Iterator<Item = Interval>> Iterator for IntersectAll<I> {
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        if self.iters.is_empty() {
+            return None;
+        }
+        loop {
+            let mut start = Value::MIN;
+            let mut end = Value::MAX;
+            let mut first_end = 0;
+            for (i, iter) in self.iters.iter_mut().enumerate() {
+                let int = *iter.peek()?;
+                start = max(start, int.0);
+                if int.1 < end {
+                    end = int.1;
+                    first_end = i;
+                }
+            }
+            // The interval that ends first cannot intersect anything after the others
+            self.iters[first_end].next();
+            if end > start {
+                return Some((start, end));
+            }
+        }
+    }
+}
+
+// The number of intervals that cover each point, as a step function of (start, end, count)
+// segments. Segments that no interval covers are left out, and adjacent segments always have
+// different counts. The intervals may overlap.
+pub fn depth<I: Iterator<Item = Interval>>(iter: I) -> Depth<I> {
+    Depth {iter: iter.peekable(), ends: BinaryHeap::new(), pos: 0, pending: None}
+}
+
+pub struct Depth<I: Iterator> {
+    iter: Peekable<I>,
+    ends: BinaryHeap<Reverse<Value>>,   // Ends of the intervals that cover pos
+    pos: Value,
+    pending: Option<(Value, Value, usize)>,     // Extended while the count stays the same
+}
+
+impl<I: Iterator<Item = Interval>> Depth<I> {
+
+    // The next segment between two consecutive starts or ends
+    fn step(&mut self) -> Option<(Value, Value, usize)> {
+        loop {
+            if self.ends.is_empty() {
+                let (start, end) = self.iter.next()?;
+                self.pos = start;
+                self.ends.push(Reverse(end));
+            }
+            while let Some(&(a, b)) = self.iter.peek() {
+                if a > self.pos {
+                    break;
+                }
+                self.ends.push(Reverse(b));
+                self.iter.next();
+            }
+            let Reverse(first_end) = *self.ends.peek()?;
+            let end = match self.iter.peek() {
+                Some(&(a, _)) => min(a, first_end),
+                None => first_end
+            };
+            let segment = (self.pos, end, self.ends.len());
+            self.pos = end;
+            while let Some(&Reverse(b)) = self.ends.peek() {
+                if b > end {
+                    break;
+                }
+                self.ends.pop();
+            }
+            if end > segment.0 {
+                return Some(segment);
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = Interval>> Iterator for Depth<I> {
+    type Item = (Value, Value, usize);
+
+    fn next(&mut self) -> Option<(Value, Value, usize)> {
+        loop {
+            let segment = match self.step() {
+                Some(segment) => segment,
+                None => return self.pending.take()
+            };
+            match self.pending {
+                Some(ref mut last) if last.1 == segment.0 && last.2 == segment.2 => {
+                    last.1 = segment.1;
+                },
+                _ => {
+                    let last = self.pending.replace(segment);
+                    if last.is_some() {
+                        return last;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The parts that are covered by at least min_depth intervals, which may overlap
+pub fn min_depth<I: Iterator<Item = Interval>>(
+    iter: I, min_depth: usize
+) -> impl Iterator<Item = Interval> {
+    coalesce(depth(iter).filter_map(
+        move |(start, end, count)| if count >= min_depth { Some((start, end)) } else { None }
+    ), 1)
+}
+
+// Extend each interval by amount at both ends, merging those that then overlap or touch, as
+// erode, closing and min_depth do
+pub fn dilate<I: Iterator<Item = Interval>>(
+    iter: I, amount: Value
+) -> impl Iterator<Item = Interval> {
+    coalesce(iter.map(
+        move |int| (int.0.saturating_sub(amount), int.1.saturating_add(amount))), 1)
+}
+
+// Shrink each interval by amount at both ends, dropping those that vanish. Intervals that touch
+// are merged first, so that erosion only happens at the boundaries of the set.
+pub fn erode<I: Iterator<Item = Interval>>(
+    iter: I, amount: Value
+) -> impl Iterator<Item = Interval> {
+    coalesce(iter, 1).filter_map(move |int| {
+        let start = int.0.saturating_add(amount);
+        let end = int.1.saturating_sub(amount);
+        if end > start { Some((start, end)) } else { None }
+    })
+}
+
+// Keep the intervals whose length is at least min_len and, if given, at most max_len
+pub fn filter_length<I: Iterator<Item = Interval>>(
+    iter: I, min_len: Value, max_len: Option<Value>
+) -> impl Iterator<Item = Interval> {
+    let lengths = LengthFilter::new(min_len, max_len);
+    iter.filter(move |int| lengths.matches(*int))
+}
+
+// Remove the parts of intervals that are at most 2 * amount long, i.e., erode and then dilate
+pub fn opening<I: Iterator<Item = Interval>>(
+    iter: I, amount: Value
+) -> impl Iterator<Item = Interval> {
+    dilate(erode(iter, amount), amount)
+}
+
+// Fill the gaps that are at most 2 * amount long, i.e., dilate and then erode. Merging the
+// intervals directly avoids shrinking those that dilation would push past 0 or Value::MAX.
+pub fn closing<I: Iterator<Item = Interval>>(
+    iter: I, amount: Value
+) -> impl Iterator<Item = Interval> {
+    coalesce(iter, amount.saturating_mul(2).saturating_add(1))
+}
@@ -0,0 +1,115 @@
+/*
+* Payload predicates
+*
+* Which intervals of a list mapping to use, based on their payload. The simplest predicate is
+* the mask and value that the queries have always taken (payload & mask == value), which a
+* (mask, value) pair converts into. Predicates can also test for a set of values or an
+* inclusive range of a masked bit field, and be combined with and, or and not. Masked values
+* are not shifted, so a range on the second byte of the payload is written as, e.g.,
+* range(0xff00, 0xc800, 0xff00).
+*/
+
+use std::fmt;
+use std::sync::Arc;
+
+use common::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PayloadPredicate {
+    Equals(Payload, Payload),               // Mask and value
+    OneOf(Payload, Vec<Payload>),           // Mask and sorted values
+    Range(Payload, Payload, Payload),       // Mask, and the minimum and maximum values
+    And(Arc<PayloadPredicate>, Arc<PayloadPredicate>),
+    Or(Arc<PayloadPredicate>, Arc<PayloadPredicate>),
+    Not(Arc<PayloadPredicate>),
+}
+
+impl PayloadPredicate {
+
+    // Matches every payload
+    pub fn any() -> Self {
+        PayloadPredicate::Equals(0, 0)
+    }
+
+    // payload & mask == value
+    pub fn equals(mask: Payload, value: Payload) -> Self {
+        PayloadPredicate::Equals(mask, value)
+    }
+
+    // payload & mask is one of values
+    pub fn one_of(mask: Payload, mut values: Vec<Payload>) -> Self {
+        values.sort_unstable();
+        values.dedup();
+        PayloadPredicate::OneOf(mask, values)
+    }
+
+    // min <= payload & mask <= max
+    pub fn range(mask: Payload, min: Payload, max: Payload) -> Self {
+        PayloadPredicate::Range(mask, min, max)
+    }
+
+    pub fn and(a: impl Into<Arc<PayloadPredicate>>, b: impl Into<Arc<PayloadPredicate>>) -> Self {
+        PayloadPredicate::And(a.into(), b.into())
+    }
+
+    pub fn or(a: impl Into<Arc<PayloadPredicate>>, b: impl Into<Arc<PayloadPredicate>>) -> Self {
+        PayloadPredicate::Or(a.into(), b.into())
+    }
+
+    pub fn not(a: impl Into<Arc<PayloadPredicate>>) -> Self {
+        PayloadPredicate::Not(a.into())
+    }
+
+    // Whether the predicate is the one that matches every payload, so filtering can be skipped
+    pub fn is_any(&self) -> bool {
+        *self == PayloadPredicate::Equals(0, 0)
+    }
+
+    pub fn matches(&self, payload: Payload) -> bool {
+        match self {
+            PayloadPredicate::Equals(mask, value) => payload & mask == *value,
+            PayloadPredicate::OneOf(mask, values) =>
+                values.binary_search(&(payload & mask)).is_ok(),
+            PayloadPredicate::Range(mask, min, max) => {
+                let masked = payload & mask;
+                *min <= masked && masked <= *max
+            },
+            PayloadPredicate::And(a, b) => a.matches(payload) && b.matches(payload),
+            PayloadPredicate::Or(a, b) => a.matches(payload) || b.matches(payload),
+            PayloadPredicate::Not(a) => !a.matches(payload),
+        }
+    }
+}
+
+impl Default for PayloadPredicate {
+    fn default() -> Self {
+        PayloadPredicate::any()
+    }
+}
+
+impl From<(Payload, Payload)> for PayloadPredicate {
+    fn from((mask, value): (Payload, Payload)) -> Self {
+        PayloadPredicate::Equals(mask, value)
+    }
+}
+
+impl fmt::Display for PayloadPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadPredicate::Equals(mask, value) =>
+                write!(f, "payload & {:#x} == {:#x}", mask, value),
+            PayloadPredicate::OneOf(mask, values) => {
+                write!(f, "payload & {:#x} in {{", mask)?;
+                for (i, value) in values.iter().enumerate() {
+                    write!(f, "{}{:#x}", if i == 0 { "" } else { ", " }, value)?;
+                }
+                write!(f, "}}")
+            },
+            PayloadPredicate::Range(mask, min, max) =>
+                write!(f, "payload & {:#x} in {:#x}..={:#x}", mask, min, max),
+            PayloadPredicate::And(a, b) => write!(f, "({} and {})", a, b),
+            PayloadPredicate::Or(a, b) => write!(f, "({} or {})", a, b),
+            PayloadPredicate::Not(a) => write!(f, "not ({})", a),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+/*
+* Read-only arrays exposed through the buffer protocol
+*
+* numpy.asarray() wraps these without copying. Arrays of unfiltered intervals point directly
+* into the mapped file, keeping the mapping alive while they exist. Other results are copied
+* into memory owned by the array.
+*/
+
+use pyo3::prelude::*;
+use pyo3::class::PyBufferProtocol;
+use pyo3::exceptions;
+use pyo3::{ffi, AsPyPointer};
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::Arc;
+
+use common::*;
+use ilistmap::MmapIntervalListMapping;
+use isetmap::MmapIntervalSetMapping;
+use payload::PayloadPredicate;
+
+enum ArrayData {
+    Owned(Vec<u8>),
+    Set(Arc<MmapIntervalSetMapping>, Id),
+    List(Arc<MmapIntervalListMapping>, Id),
+}
+
+#[pyclass]
+pub struct Array {
+    data: ArrayData,
+    offset: usize,          // Offset of the first element in the data
+    format: &'static [u8],  // Nul terminated struct format of an element
+    itemsize: usize,
+    shape: Vec<isize>,
+    strides: Vec<isize>,
+}
+
+// Struct formats of little endian unsigned integers, by width
+fn uint_format(width: usize) -> &'static [u8] {
+    match width {
+        1 => b"<B\0",
+        2 => b"<H\0",
+        4 => b"<I\0",
+        _ => b"<Q\0"
+    }
+}
+
+impl Array {
+
+    // An (n, 2) array of the intervals for an id
+    pub fn from_set(mapping: Arc<MmapIntervalSetMapping>, id: Id) -> Self {
+        let n = mapping.get_interval_count(id).unwrap_or(0);
+        Array::intervals(ArrayData::Set(mapping, id), n, INTERVAL_SIZE)
+    }
+
+    // An (n, 2) array of the intervals for an id, skipping the payloads
+    pub fn from_list(mapping: Arc<MmapIntervalListMapping>, id: Id) -> Self {
+        let n = mapping.get_interval_count(id, &PayloadPredicate::any()).unwrap_or(0);
+        let record_size = INTERVAL_SIZE + mapping.get_payload_len();
+        Array::intervals(ArrayData::List(mapping, id), n, record_size)
+    }
+
+    // An (n,) array of the payloads for an id. Payloads that are 1, 2, 4 or 8 bytes long are
+    // not copied; others are copied into the smallest unsigned integer that fits them.
+    pub fn from_list_payloads(mapping: Arc<MmapIntervalListMapping>, id: Id) -> Self {
+        let payload_len = mapping.get_payload_len();
+        let record_size = INTERVAL_SIZE + payload_len;
+        let n = mapping.get_interval_count(id, &PayloadPredicate::any()).unwrap_or(0);
+        if [1, 2, 4, 8].contains(&payload_len) {
+            return Array {
+                data: ArrayData::List(mapping, id), offset: INTERVAL_SIZE,
+                format: uint_format(payload_len), itemsize: payload_len,
+                shape: vec![n as isize], strides: vec![record_size as isize]
+            };
+        }
+        let width = match payload_len {
+            0 => 1,
+            3 => 4,
+            _ => mem::size_of::<Payload>()
+        };
+        let mut buf = vec![0u8; n * width];
+        if let Ok(bytes) = mapping.get_interval_bytes(id) {
+            for i in 0..n {
+                let payload = &bytes[i * record_size + INTERVAL_SIZE..(i + 1) * record_size];
+                buf[i * width..i * width + payload_len].copy_from_slice(payload);
+            }
+        }
+        Array::owned(buf, width, vec![n as isize])
+    }
+
+    // An (n, 2) array of intervals held in memory
+    pub fn from_intervals(intervals: &[Interval]) -> Self {
+        let mut buf = Vec::with_capacity(intervals.len() * INTERVAL_SIZE);
+        for (a, b) in intervals {
+            buf.extend_from_slice(&a.to_le_bytes());
+            buf.extend_from_slice(&b.to_le_bytes());
+        }
+        Array::owned(buf, mem::size_of::<Value>(), vec![intervals.len() as isize, 2])
+    }
+
+    fn intervals(data: ArrayData, n: usize, record_size: usize) -> Self {
+        let itemsize = mem::size_of::<Value>();
+        Array {
+            data, offset: 0, format: uint_format(itemsize), itemsize,
+            shape: vec![n as isize, 2], strides: vec![record_size as isize, itemsize as isize]
+        }
+    }
+
+    // A C contiguous array
+    fn owned(buf: Vec<u8>, itemsize: usize, shape: Vec<isize>) -> Self {
+        let mut strides = vec![itemsize as isize; shape.len()];
+        for i in (0..shape.len() - 1).rev() {
+            strides[i] = strides[i + 1] * shape[i + 1];
+        }
+        Array {
+            data: ArrayData::Owned(buf), offset: 0, format: uint_format(itemsize), itemsize,
+            shape, strides
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match &self.data {
+            ArrayData::Owned(buf) => buf,
+            ArrayData::Set(mapping, id) => mapping.get_interval_bytes(*id).unwrap_or(&[]),
+            ArrayData::List(mapping, id) => mapping.get_interval_bytes(*id).unwrap_or(&[]),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.shape.iter().product::<isize>() as usize
+    }
+
+    fn is_contiguous(&self) -> bool {
+        let mut stride = self.itemsize as isize;
+        for i in (0..self.shape.len()).rev() {
+            if self.shape[i] > 1 && self.strides[i] != stride {
+                return false;
+            }
+            stride *= self.shape[i];
+        }
+        true
+    }
+
+    // Convert to a numpy array, without copying
+    pub fn into_numpy(self, py: Python) -> PyResult<PyObject> {
+        let array = Py::new(py, self)?;
+        Ok(py.import("numpy")?.call1("asarray", (array,))?.to_object(py))
+    }
+}
+
+#[pyproto]
+impl PyBufferProtocol for Array {
+    fn bf_getbuffer(
+        slf: PyRefMut<Self>, view: *mut ffi::Py_buffer, flags: c_int
+    ) -> PyResult<()> {
+        if view.is_null() {
+            return Err(exceptions::BufferError::py_err("view is null"));
+        }
+        if flags & ffi::PyBUF_WRITABLE == ffi::PyBUF_WRITABLE {
+            return Err(exceptions::BufferError::py_err("array is read-only"));
+        }
+        let contiguous = slf.is_contiguous();
+        if !contiguous && (flags & ffi::PyBUF_STRIDES != ffi::PyBUF_STRIDES
+                           || flags & ffi::PyBUF_ANY_CONTIGUOUS == ffi::PyBUF_ANY_CONTIGUOUS
+                           || flags & ffi::PyBUF_C_CONTIGUOUS == ffi::PyBUF_C_CONTIGUOUS) {
+            return Err(exceptions::BufferError::py_err("array is not contiguous"));
+        }
+        if flags & ffi::PyBUF_F_CONTIGUOUS == ffi::PyBUF_F_CONTIGUOUS
+            && slf.shape.len() > 1 && slf.shape[0] > 1 {
+            return Err(exceptions::BufferError::py_err("array is not Fortran contiguous"));
+        }
+
+        let bytes = slf.bytes();
+        let buf = bytes.get(slf.offset..).unwrap_or(bytes).as_ptr();
+        unsafe {
+            (*view).obj = slf.as_ptr();
+            ffi::Py_INCREF((*view).obj);
+            (*view).buf = buf as *mut c_void;
+            (*view).len = (slf.len() * slf.itemsize) as isize;
+            (*view).readonly = 1;
+            (*view).itemsize = slf.itemsize as isize;
+            (*view).format = if flags & ffi::PyBUF_FORMAT == ffi::PyBUF_FORMAT {
+                CStr::from_bytes_with_nul(slf.format).unwrap().as_ptr() as *mut _
+            } else {
+                ptr::null_mut()
+            };
+            (*view).ndim = slf.shape.len() as c_int;
+            (*view).shape = if flags & ffi::PyBUF_ND == ffi::PyBUF_ND {
+                slf.shape.as_ptr() as *mut _
+            } else {
+                ptr::null_mut()
+            };
+            (*view).strides = if flags & ffi::PyBUF_STRIDES == ffi::PyBUF_STRIDES {
+                slf.strides.as_ptr() as *mut _
+            } else {
+                ptr::null_mut()
+            };
+            (*view).suboffsets = ptr::null_mut();
+            (*view).internal = ptr::null_mut();
+        }
+        Ok(())
+    }
+
+    fn bf_releasebuffer(_slf: PyRefMut<Self>, _view: *mut ffi::Py_buffer) {}
+}
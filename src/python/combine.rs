@@ -0,0 +1,169 @@
+/*
+* Python wrappers for the combined mappings
+*/
+
+use pyo3::prelude::*;
+use pyo3::types::PyAny;
+use std::sync::Arc;
+
+use common::*;
+use combine::*;
+use python::array::Array;
+use python::convert::extract_intervals;
+use python::ilistmap::PyMmapIntervalListMapping;
+use python::isetmap::PyMmapIntervalSetMapping;
+use python::or_default;
+use python::payload::extract_optional_payload;
+
+// Base class of the combined mappings, which only differ in how they are constructed
+#[pyclass(name=CombinedMapping, subclass)]
+pub struct PyCombinedMapping {
+    pub(crate) _impl: Arc<dyn CombinedMapping + Send + Sync>,
+}
+
+impl PyCombinedMapping {
+
+    fn new<T: CombinedMapping + Send + Sync + 'static>(mapping: T) -> Self {
+        PyCombinedMapping {_impl: Arc::new(mapping)}
+    }
+}
+
+#[pymethods]
+impl PyCombinedMapping {
+
+    fn len(&self) -> PyResult<usize> {
+        Ok(self._impl.len())
+    }
+
+    fn get_ids(&self) -> PyResult<Vec<Id>> {
+        Ok(self._impl.get_ids().to_vec())
+    }
+
+    fn has_id(&self, id: Id) -> PyResult<bool> {
+        Ok(self._impl.has_id(id))
+    }
+
+    fn sum(&self, py: Python) -> PyResult<u64> {
+        Ok(py.allow_threads(|| self._impl.sum()))
+    }
+
+    // Get the number of intervals for an id, after merging
+    fn get_interval_count(&self, py: Python, id: Id) -> PyResult<usize> {
+        Ok(py.allow_threads(|| self._impl.get_interval_count(id))?)
+    }
+
+    fn get_intervals(&self, py: Python, id: Id, use_default: bool) -> PyResult<Vec<Interval>> {
+        or_default(py.allow_threads(|| self._impl.get_intervals(id)), use_default, vec![])
+    }
+
+    // Same as get_intervals, but returns an (n, 2) numpy array
+    fn get_intervals_array(&self, py: Python, id: Id, use_default: bool) -> PyResult<PyObject> {
+        let res = self.get_intervals(py, id, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Get whether a target is in any of the merged intervals
+    fn is_contained(&self, py: Python, id: Id, target: Value,
+                    use_default: bool) -> PyResult<bool> {
+        or_default(py.allow_threads(|| self._impl.is_contained(id, target)), use_default, false)
+    }
+
+    // Get whether start and end intersect with any of the merged intervals
+    fn has_intersection(&self, py: Python, id: Id, start: Value, end: Value,
+                        use_default: bool) -> PyResult<bool> {
+        or_default(
+            py.allow_threads(|| self._impl.has_intersection(id, start, end)), use_default, false)
+    }
+
+    // Intersect a sorted list of intervals, either as a list of tuples or an (n, 2) array
+    fn intersect(&self, py: Python, id: Id, intervals: &PyAny,
+                 use_default: bool) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        or_default(
+            py.allow_threads(|| self._impl.intersect(id, &intervals)), use_default, vec![])
+    }
+
+    // Same as intersect, but returns an (n, 2) numpy array
+    fn intersect_array(&self, py: Python, id: Id, intervals: &PyAny,
+                       use_default: bool) -> PyResult<PyObject> {
+        let res = self.intersect(py, id, intervals, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Intersect and then sum
+    fn intersect_sum(&self, py: Python, id: Id, intervals: &PyAny,
+                     use_default: bool) -> PyResult<u64> {
+        let intervals = extract_intervals(intervals)?;
+        or_default(
+            py.allow_threads(|| self._impl.intersect_sum(id, &intervals)), use_default, 0)
+    }
+
+    // Minus this from intervals, either as a list of tuples or an (n, 2) array
+    fn minus(&self, py: Python, id: Id, intervals: &PyAny,
+             use_default: bool) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        let res = py.allow_threads(|| self._impl.minus(id, &intervals));
+        or_default(res, use_default, intervals)
+    }
+
+    // Same as minus, but returns an (n, 2) numpy array
+    fn minus_array(&self, py: Python, id: Id, intervals: &PyAny,
+                   use_default: bool) -> PyResult<PyObject> {
+        let res = self.minus(py, id, intervals, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+}
+
+#[pyclass(name=ListUnionMapping, extends=PyCombinedMapping)]
+pub struct PyListUnionMapping {}
+
+#[pymethods]
+impl PyListUnionMapping {
+
+    // Only intervals whose payload matches (payload & payload_mask == payload_value, or
+    // payload_mask is a PayloadPredicate) are included. Intervals less than fuzz apart are merged,
+    // but the queries only merge the parts of them that they return (see ListUnionMapping).
+    #[new]
+    #[args(payload_mask = "None", payload_value = "None", fuzz = "0")]
+    fn new(
+        py: Python, mappings: Vec<PyRef<PyMmapIntervalListMapping>>,
+        payload_mask: Option<&PyAny>, payload_value: Option<Payload>, fuzz: Value
+    ) -> PyResult<(Self, PyCombinedMapping)> {
+        let payload = extract_optional_payload(payload_mask, payload_value)?;
+        let mappings: Vec<_> = mappings.iter().map(|m| m._impl.clone()).collect();
+        let union = py.allow_threads(|| ListUnionMapping::new(mappings, payload, fuzz));
+        Ok((PyListUnionMapping {}, PyCombinedMapping::new(union)))
+    }
+}
+
+#[pyclass(name=SetUnionMapping, extends=PyCombinedMapping)]
+pub struct PySetUnionMapping {}
+
+#[pymethods]
+impl PySetUnionMapping {
+
+    #[new]
+    fn new(
+        py: Python, mappings: Vec<PyRef<PyMmapIntervalSetMapping>>
+    ) -> (Self, PyCombinedMapping) {
+        let mappings: Vec<_> = mappings.iter().map(|m| m._impl.clone()).collect();
+        let union = py.allow_threads(|| SetUnionMapping::new(mappings));
+        (PySetUnionMapping {}, PyCombinedMapping::new(union))
+    }
+}
+
+#[pyclass(name=SetIntersectionMapping, extends=PyCombinedMapping)]
+pub struct PySetIntersectionMapping {}
+
+#[pymethods]
+impl PySetIntersectionMapping {
+
+    #[new]
+    fn new(
+        py: Python, mappings: Vec<PyRef<PyMmapIntervalSetMapping>>
+    ) -> (Self, PyCombinedMapping) {
+        let mappings: Vec<_> = mappings.iter().map(|m| m._impl.clone()).collect();
+        let intersection = py.allow_threads(|| SetIntersectionMapping::new(mappings));
+        (PySetIntersectionMapping {}, PyCombinedMapping::new(intersection))
+    }
+}
@@ -0,0 +1,103 @@
+/*
+* Conversions from Python objects to intervals and load options
+*
+* Objects that expose an (n, k) integer array through the buffer protocol (e.g., numpy arrays)
+* are read directly from their memory instead of element by element.
+*/
+
+use pyo3::prelude::*;
+use pyo3::buffer::{Element, PyBuffer};
+use pyo3::exceptions;
+use pyo3::{ffi, AsPyPointer, PyNativeType};
+use pyo3::types::PyAny;
+use std::convert::TryFrom;
+
+use common::*;
+
+fn read_buffer<T>(py: Python, buf: &PyBuffer<T>, width: usize) -> PyResult<Vec<u64>>
+    where T: Element, u64: TryFrom<T>
+{
+    let shape_ok = match buf.dimensions() {
+        1 => width == 1,
+        2 => buf.shape()[1] == width,
+        _ => false
+    };
+    if !shape_ok {
+        return Err(exceptions::ValueError::py_err(if width == 1 {
+            "expected an array of shape (n,)".to_owned()
+        } else {
+            format!("expected an array of shape (n, {})", width)
+        }));
+    }
+    buf.to_vec(py)?.into_iter().map(|v| u64::try_from(v).map_err(
+        |_| exceptions::ValueError::py_err("negative value in array")
+    )).collect()
+}
+
+// Read the rows of an (n, width) integer array, or of an (n,) array if width is 1, or None if
+// obj does not support the buffer protocol
+fn extract_array(obj: &PyAny, width: usize) -> PyResult<Option<Vec<u64>>> {
+    if unsafe { ffi::PyObject_CheckBuffer(obj.as_ptr()) } == 0 {
+        return Ok(None);
+    }
+    let py = obj.py();
+    if let Ok(buf) = PyBuffer::<u32>::get(obj) {
+        return read_buffer(py, &buf, width).map(Some);
+    }
+    if let Ok(buf) = PyBuffer::<i64>::get(obj) {
+        return read_buffer(py, &buf, width).map(Some);
+    }
+    if let Ok(buf) = PyBuffer::<u64>::get(obj) {
+        return read_buffer(py, &buf, width).map(Some);
+    }
+    if let Ok(buf) = PyBuffer::<i32>::get(obj) {
+        return read_buffer(py, &buf, width).map(Some);
+    }
+    Err(exceptions::TypeError::py_err("unsupported array type, expected 32 or 64 bit integers"))
+}
+
+fn to_value(v: u64) -> PyResult<Value> {
+    if v > Value::MAX as u64 {
+        Err(exceptions::OverflowError::py_err(format!("value out of range: {}", v)))
+    } else {
+        Ok(v as Value)
+    }
+}
+
+pub fn extract_values(obj: &PyAny) -> PyResult<Vec<Value>> {
+    match extract_array(obj, 1)? {
+        Some(values) => values.into_iter().map(to_value).collect(),
+        None => obj.extract()
+    }
+}
+
+pub fn extract_intervals(obj: &PyAny) -> PyResult<Vec<Interval>> {
+    match extract_array(obj, 2)? {
+        Some(values) => values.chunks(2).map(
+            |row| Ok((to_value(row[0])?, to_value(row[1])?))
+        ).collect(),
+        None => obj.extract()
+    }
+}
+
+pub fn extract_intervals_with_payload(obj: &PyAny) -> PyResult<Vec<(Value, Value, Payload)>> {
+    match extract_array(obj, 3)? {
+        Some(values) => values.chunks(3).map(
+            |row| Ok((to_value(row[0])?, to_value(row[1])?, row[2]))
+        ).collect(),
+        None => obj.extract()
+    }
+}
+
+// Get the bound for an id, which is either given as a number or looked up in a table of
+// bounds indexed by id (e.g., a dict or a list)
+pub fn extract_bound(obj: &PyAny, id: Id) -> PyResult<Value> {
+    match obj.extract() {
+        Ok(bound) => Ok(bound),
+        Err(_) => obj.get_item(id)?.extract()
+    }
+}
+
+pub fn parse_duplicates(s: &str) -> PyResult<Duplicates> {
+    s.parse().map_err(exceptions::ValueError::py_err)
+}
@@ -0,0 +1,133 @@
+/*
+* Python wrapper for query expressions
+*
+* Expressions are combined with &, | and -, and derive from CombinedMapping for the queries.
+*/
+
+use pyo3::prelude::*;
+use pyo3::class::{PyNumberProtocol, PyObjectProtocol};
+use pyo3::exceptions;
+use pyo3::PyNativeType;
+use pyo3::types::PyAny;
+use std::sync::Arc;
+
+use common::*;
+use expr::Expr;
+use payload::PayloadPredicate;
+use query::Binding;
+use python::combine::PyCombinedMapping;
+use python::ilistmap::PyMmapIntervalListMapping;
+use python::isetmap::PyMmapIntervalSetMapping;
+use python::payload::extract_optional_payload;
+
+#[pyclass(name=Expr, extends=PyCombinedMapping)]
+pub struct PyExpr {
+    expr: Arc<Expr>,
+}
+
+impl PyExpr {
+
+    fn init(expr: Arc<Expr>) -> (Self, PyCombinedMapping) {
+        (PyExpr {expr: expr.clone()}, PyCombinedMapping {_impl: expr})
+    }
+
+    fn create(py: Python, expr: Expr) -> PyResult<PyObject> {
+        PyExpr::wrap(py, Arc::new(expr))
+    }
+
+    pub fn wrap(py: Python, expr: Arc<Expr>) -> PyResult<PyObject> {
+        Ok(Py::new(py, PyExpr::init(expr))?.to_object(py))
+    }
+
+    // What obj refers to, as a leaf or a name bound in a query
+    pub fn binding(obj: &PyAny) -> PyResult<Binding> {
+        if let Ok(e) = obj.extract::<PyRef<PyExpr>>() {
+            Ok(Binding::Expr(e.expr.clone()))
+        } else if let Ok(m) = obj.extract::<PyRef<PyMmapIntervalSetMapping>>() {
+            Ok(Binding::Set(m._impl.clone()))
+        } else if let Ok(m) = obj.extract::<PyRef<PyMmapIntervalListMapping>>() {
+            Ok(Binding::List(m._impl.clone()))
+        } else if let Ok(m) = obj.extract::<PyRef<PyCombinedMapping>>() {
+            Ok(Binding::Mapping(m._impl.clone()))
+        } else {
+            Err(exceptions::TypeError::py_err(
+                "expected an Expr, MmapIntervalSetMapping, MmapIntervalListMapping or \
+                 CombinedMapping"))
+        }
+    }
+
+    // Convert an expression or a mapping, whose intervals are used unfiltered
+    fn extract(obj: &PyAny) -> PyResult<Arc<Expr>> {
+        PyExpr::leaf(obj, PayloadPredicate::any())
+    }
+
+    fn leaf(obj: &PyAny, payload: PayloadPredicate) -> PyResult<Arc<Expr>> {
+        Ok(match PyExpr::binding(obj)? {
+            Binding::Expr(e) => e,
+            Binding::Set(m) => Arc::new(Expr::set(m)),
+            Binding::List(m) => Arc::new(Expr::list(m, payload)),
+            Binding::Mapping(m) => Arc::new(Expr::mapping(m)),
+        })
+    }
+}
+
+#[pymethods]
+impl PyExpr {
+
+    // Extend each interval by amount at both ends
+    fn dilate(&self, py: Python, amount: Value) -> PyResult<PyObject> {
+        PyExpr::create(py, Expr::dilate(self.expr.clone(), amount))
+    }
+
+    // Shrink each interval by amount at both ends, dropping those that vanish
+    fn erode(&self, py: Python, amount: Value) -> PyResult<PyObject> {
+        PyExpr::create(py, Expr::erode(self.expr.clone(), amount))
+    }
+
+    // Merge intervals that are less than gap apart
+    fn coalesce(&self, py: Python, gap: Value) -> PyResult<PyObject> {
+        PyExpr::create(py, Expr::coalesce(self.expr.clone(), gap))
+    }
+
+    // Keep the intervals whose length is at least min_len and, if given, at most max_len
+    #[args(min_len = "0", max_len = "None")]
+    fn filter_length(
+        &self, py: Python, min_len: Value, max_len: Option<Value>
+    ) -> PyResult<PyObject> {
+        PyExpr::create(py, Expr::filter_length(self.expr.clone(), min_len, max_len))
+    }
+
+    // A leaf over a mapping. For a list mapping, only intervals whose payload matches
+    // (payload & payload_mask == payload_value, or payload_mask is a PayloadPredicate) are
+    // included, and overlapping ones are merged.
+    #[new]
+    #[args(payload_mask = "None", payload_value = "None")]
+    fn new(
+        mapping: &PyAny, payload_mask: Option<&PyAny>, payload_value: Option<Payload>
+    ) -> PyResult<(Self, PyCombinedMapping)> {
+        let payload = extract_optional_payload(payload_mask, payload_value)?;
+        Ok(PyExpr::init(PyExpr::leaf(mapping, payload)?))
+    }
+}
+
+#[pyproto]
+impl<'p> PyNumberProtocol<'p> for PyExpr {
+    fn __and__(lhs: &'p PyAny, rhs: &'p PyAny) -> PyResult<PyObject> {
+        PyExpr::create(lhs.py(), Expr::and(PyExpr::extract(lhs)?, PyExpr::extract(rhs)?))
+    }
+
+    fn __or__(lhs: &'p PyAny, rhs: &'p PyAny) -> PyResult<PyObject> {
+        PyExpr::create(lhs.py(), Expr::or(PyExpr::extract(lhs)?, PyExpr::extract(rhs)?))
+    }
+
+    fn __sub__(lhs: &'p PyAny, rhs: &'p PyAny) -> PyResult<PyObject> {
+        PyExpr::create(lhs.py(), Expr::minus(PyExpr::extract(lhs)?, PyExpr::extract(rhs)?))
+    }
+}
+
+#[pyproto]
+impl<'p> PyObjectProtocol<'p> for PyExpr {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("Expr({})", self.expr))
+    }
+}
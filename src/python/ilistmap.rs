@@ -0,0 +1,407 @@
+/*
+* Python wrapper for MmapIntervalListMapping
+*/
+
+use pyo3::prelude::*;
+use pyo3::types::PyAny;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use common::*;
+use ilistmap::{IntervalAndPayload, MmapIntervalListMapping};
+use payload::PayloadPredicate;
+use python::array::Array;
+use python::convert::{extract_bound, extract_intervals, extract_values, parse_duplicates};
+use python::or_default;
+use python::payload::extract_payload;
+
+#[pyclass(name=MmapIntervalListMapping)]
+pub struct PyMmapIntervalListMapping {
+    pub(crate) _impl: Arc<MmapIntervalListMapping>
+}
+
+#[pymethods]
+impl PyMmapIntervalListMapping {
+
+    fn len(&self) -> PyResult<usize> {
+        Ok(self._impl.len())
+    }
+
+    fn get_ids(&self, py: Python) -> PyResult<Vec<Id>> {
+        Ok(py.allow_threads(|| self._impl.ids().collect()))
+    }
+
+    fn has_id(&self, id: Id) -> PyResult<bool> {
+        Ok(self._impl.has_id(id))
+    }
+
+    fn get_payload_len(&self) -> PyResult<usize> {
+        Ok(self._impl.get_payload_len())
+    }
+
+    // Sum the lengths of the intervals whose payload matches and whose length is in
+    // [min_len, max_len]
+    #[args(min_len = "0", max_len = "None")]
+    fn sum(
+        &self, py: Python, payload_mask: &PyAny, payload_value: Option<Payload>, min_len: Value,
+        max_len: Option<Value>
+    ) -> PyResult<u64> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let lengths = LengthFilter::new(min_len, max_len);
+        Ok(py.allow_threads(|| self._impl.sum(&payload, lengths)))
+    }
+
+    // Sum the lengths of the intervals for an id, or for all ids if id is None, grouped by
+    // payload & payload_mask. Returns a dict from each group to its total length and number of
+    // intervals. If deoverlap is true, overlapping intervals in a group are merged first.
+    #[args(deoverlap = "false", use_default = "false")]
+    fn sum_by_payload(
+        &self, py: Python, id: Option<Id>, payload_mask: Payload, deoverlap: bool,
+        use_default: bool
+    ) -> PyResult<HashMap<Payload, (u64, usize)>> {
+        or_default(
+            py.allow_threads(|| self._impl.sum_by_payload(id, payload_mask, deoverlap)),
+            use_default, HashMap::new())
+    }
+
+    // Get the number of intervals for an id
+    fn get_interval_count(
+        &self, py: Python, id: Id, payload_mask: &PyAny, payload_value: Option<Payload>
+    ) -> PyResult<usize> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        Ok(py.allow_threads(
+            || self._impl.get_interval_count(id, &payload))?)
+    }
+
+    // Get the intervals for an id whose payload matches and whose length is in
+    // [min_len, max_len]
+    #[allow(clippy::too_many_arguments)]
+    #[args(min_len = "0", max_len = "None")]
+    fn get_intervals(
+        &self, py: Python, id: Id, payload_mask: &PyAny, payload_value: Option<Payload>,
+        use_default: bool, min_len: Value, max_len: Option<Value>
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let lengths = LengthFilter::new(min_len, max_len);
+        or_default(
+            py.allow_threads(
+                || self._impl.get_intervals(id, &payload, lengths)),
+            use_default, vec![])
+    }
+
+    fn get_intervals_with_payload(
+        &self, py: Python, id: Id, use_default: bool
+    ) -> PyResult<Vec<IntervalAndPayload>> {
+        or_default(
+            py.allow_threads(|| self._impl.get_intervals_with_payload(id)), use_default, vec![])
+    }
+
+    // Same as get_intervals, but returns an (n, 2) numpy array, which is a view of the file if
+    // no payload or length filter is given
+    #[allow(clippy::too_many_arguments)]
+    #[args(min_len = "0", max_len = "None")]
+    fn get_intervals_array(
+        &self, py: Python, id: Id, payload_mask: &PyAny, payload_value: Option<Payload>,
+        use_default: bool, min_len: Value, max_len: Option<Value>
+    ) -> PyResult<PyObject> {
+        let lengths = LengthFilter::new(min_len, max_len);
+        if extract_payload(payload_mask, payload_value)?.is_any() && lengths.is_any() {
+            or_default(
+                self._impl.get_interval_count(id, &PayloadPredicate::any()), use_default, 0)?;
+            return Array::from_list(self._impl.clone(), id).into_numpy(py);
+        }
+        let res = self.get_intervals(
+            py, id, payload_mask, payload_value, use_default, min_len, max_len)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Get the intervals for an id as an (n, 2) numpy array and their payloads as an (n,) numpy
+    // array. Both are views of the file, unless the payload length is not 1, 2, 4 or 8 bytes,
+    // in which case the payloads are copied.
+    fn get_intervals_with_payload_array(
+        &self, py: Python, id: Id, use_default: bool
+    ) -> PyResult<(PyObject, PyObject)> {
+        or_default(
+            self._impl.get_interval_count(id, &PayloadPredicate::any()), use_default, 0)?;
+        Ok((
+            Array::from_list(self._impl.clone(), id).into_numpy(py)?,
+            Array::from_list_payloads(self._impl.clone(), id).into_numpy(py)?
+        ))
+    }
+
+    // Get the number of intervals whose payload matches that cover each point, as a list of
+    // (start, end, count) segments. Points that are not covered are left out.
+    fn depth_profile(
+        &self, py: Python, id: Id, payload_mask: &PyAny, payload_value: Option<Payload>,
+        use_default: bool
+    ) -> PyResult<Vec<(Value, Value, usize)>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.depth_profile(id, &payload)),
+            use_default, vec![])
+    }
+
+    // Get the parts that are covered by at least min_depth intervals whose payload matches, as
+    // a set of intervals
+    fn min_depth(
+        &self, py: Python, id: Id, min_depth: usize, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(
+                || self._impl.min_depth(id, min_depth, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as min_depth, but returns an (n, 2) numpy array
+    fn min_depth_array(
+        &self, py: Python, id: Id, min_depth: usize, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<PyObject> {
+        let res = self.min_depth(py, id, min_depth, payload_mask, payload_value, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Get whether target is in any interval whose payload matches. search_window is no longer
+    // needed, and passing it is deprecated.
+    #[allow(clippy::too_many_arguments)]
+    #[args(search_window = "None")]
+    fn is_contained(
+        &self, py: Python, id: Id, target: Value, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool, search_window: Option<Value>
+    ) -> PyResult<bool> {
+        if search_window.is_some() {
+            let category = py.import("builtins")?.get("DeprecationWarning")?;
+            PyErr::warn(py, category, "search_window is ignored and will be removed", 1)?;
+        }
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.is_contained(id, target, &payload)),
+            use_default, false)
+    }
+
+    // Get the intervals, with their payloads, that contain target and whose payload matches
+    fn stab(
+        &self, py: Python, id: Id, target: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<IntervalAndPayload>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.stab(id, target, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as stab, for each of several targets, either as a list or an (n,) array
+    fn stab_many(
+        &self, py: Python, id: Id, targets: &PyAny, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Vec<IntervalAndPayload>>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let targets = extract_values(targets)?;
+        let n = targets.len();
+        or_default(
+            py.allow_threads(
+                || self._impl.stab_many(id, &targets, &payload)),
+            use_default, vec![vec![]; n])
+    }
+
+    // Get the intervals, with their payloads, that overlap [start, end) and whose payload
+    // matches. They are not clipped to the window.
+    #[allow(clippy::too_many_arguments)]
+    fn overlapping(
+        &self, py: Python, id: Id, start: Value, end: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<IntervalAndPayload>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.overlapping(id, start, end, &payload)),
+            use_default, vec![])
+    }
+
+    // Get the parts of [lo, hi) that are not in any interval for an id whose payload matches.
+    // hi is either a number or a table of bounds indexed by id.
+    #[allow(clippy::too_many_arguments)]
+    fn complement(
+        &self, py: Python, id: Id, lo: Value, hi: &PyAny, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let hi = extract_bound(hi, id)?;
+        or_default(
+            py.allow_threads(|| self._impl.complement(id, lo, hi, &payload)),
+            use_default, if hi > lo { vec![(lo, hi)] } else { vec![] })
+    }
+
+    // Complement and then sum
+    #[allow(clippy::too_many_arguments)]
+    fn complement_sum(
+        &self, py: Python, id: Id, lo: Value, hi: &PyAny, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<u64> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let hi = extract_bound(hi, id)?;
+        or_default(
+            py.allow_threads(|| self._impl.complement_sum(id, lo, hi, &payload)),
+            use_default, hi.saturating_sub(lo) as u64)
+    }
+
+    // Extend the intervals whose payload matches by amount at both ends, merging overlaps
+    fn dilate(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.dilate(id, amount, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as dilate, but returns an (n, 2) numpy array
+    fn dilate_array(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<PyObject> {
+        let res = self.dilate(py, id, amount, payload_mask, payload_value, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Shrink the intervals whose payload matches by amount at both ends, dropping empty ones
+    fn erode(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.erode(id, amount, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as erode, but returns an (n, 2) numpy array
+    fn erode_array(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<PyObject> {
+        let res = self.erode(py, id, amount, payload_mask, payload_value, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Merge the intervals whose payload matches that are less than gap apart
+    fn coalesce(
+        &self, py: Python, id: Id, gap: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.coalesce(id, gap, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as coalesce, but returns an (n, 2) numpy array
+    fn coalesce_array(
+        &self, py: Python, id: Id, gap: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<PyObject> {
+        let res = self.coalesce(py, id, gap, payload_mask, payload_value, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Remove the parts of the matching intervals that are at most 2 * amount long
+    fn opening(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.opening(id, amount, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as opening, but returns an (n, 2) numpy array
+    fn opening_array(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<PyObject> {
+        let res = self.opening(py, id, amount, payload_mask, payload_value, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Fill the gaps between the matching intervals that are at most 2 * amount long
+    fn closing(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        or_default(
+            py.allow_threads(|| self._impl.closing(id, amount, &payload)),
+            use_default, vec![])
+    }
+
+    // Same as closing, but returns an (n, 2) numpy array
+    fn closing_array(
+        &self, py: Python, id: Id, amount: Value,
+        payload_mask: &PyAny, payload_value: Option<Payload>, use_default: bool
+    ) -> PyResult<PyObject> {
+        let res = self.closing(py, id, amount, payload_mask, payload_value, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Intersect a sorted list of intervals, either as a list of tuples or an (n, 2) array. Only
+    // the intervals whose length is in [min_len, max_len] are included.
+    #[allow(clippy::too_many_arguments)]
+    #[args(min_len = "0", max_len = "None")]
+    fn intersect(
+        &self, py: Python, id: Id, intervals: &PyAny, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool, min_len: Value, max_len: Option<Value>
+    ) -> PyResult<Vec<Interval>> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let intervals = extract_intervals(intervals)?;
+        let lengths = LengthFilter::new(min_len, max_len);
+        or_default(
+            py.allow_threads(
+                || self._impl.intersect(id, &intervals, &payload, lengths)),
+            use_default, vec![])
+    }
+
+    // Same as intersect, but returns an (n, 2) numpy array
+    #[allow(clippy::too_many_arguments)]
+    #[args(min_len = "0", max_len = "None")]
+    fn intersect_array(
+        &self, py: Python, id: Id, intervals: &PyAny, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool, min_len: Value, max_len: Option<Value>
+    ) -> PyResult<PyObject> {
+        let res = self.intersect(
+            py, id, intervals, payload_mask, payload_value, use_default, min_len, max_len)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Intersect and then sum. Only the intervals whose length is in [min_len, max_len] are
+    // included.
+    #[allow(clippy::too_many_arguments)]
+    #[args(min_len = "0", max_len = "None")]
+    fn intersect_sum(
+        &self, py: Python, id: Id, intervals: &PyAny, payload_mask: &PyAny,
+        payload_value: Option<Payload>, use_default: bool, min_len: Value, max_len: Option<Value>
+    ) -> PyResult<u64> {
+        let payload = extract_payload(payload_mask, payload_value)?;
+        let intervals = extract_intervals(intervals)?;
+        let lengths = LengthFilter::new(min_len, max_len);
+        or_default(
+            py.allow_threads(|| self._impl.intersect_sum(
+                id, &intervals, &payload, lengths)),
+            use_default, 0)
+    }
+
+    // The payload length is only required for files without a header. Duplicate ids are
+    // handled according to duplicates: "error", "first", "last" (the default) or "merge".
+    #[new]
+    #[args(payload_len = "None", duplicates = "\"last\"")]
+    fn new(
+        py: Python, data_file: String, payload_len: Option<usize>, duplicates: &str
+    ) -> PyResult<Self> {
+        let duplicates = parse_duplicates(duplicates)?;
+        let mapping = py.allow_threads(
+            || MmapIntervalListMapping::open(&data_file, payload_len, duplicates))?;
+        Ok(PyMmapIntervalListMapping {_impl: Arc::new(mapping)})
+    }
+}
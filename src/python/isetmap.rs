@@ -0,0 +1,287 @@
+/*
+* Python wrapper for MmapIntervalSetMapping
+*/
+
+use pyo3::prelude::*;
+use pyo3::types::PyAny;
+use std::sync::Arc;
+
+use common::*;
+use isetmap::MmapIntervalSetMapping;
+use ops;
+use python::array::Array;
+use python::convert::{extract_bound, extract_intervals, parse_duplicates};
+use python::or_default;
+
+#[pyclass(name=MmapIntervalSetMapping)]
+pub struct PyMmapIntervalSetMapping {
+    pub(crate) _impl: Arc<MmapIntervalSetMapping>,
+}
+
+#[pymethods]
+impl PyMmapIntervalSetMapping {
+
+    fn len(&self) -> PyResult<usize> {
+        Ok(self._impl.len())
+    }
+
+    fn get_ids(&self, py: Python) -> PyResult<Vec<Id>> {
+        Ok(py.allow_threads(|| self._impl.ids().collect()))
+    }
+
+    fn has_id(&self, id: Id) -> PyResult<bool> {
+        Ok(self._impl.has_id(id))
+    }
+
+    // Sum the lengths of the intervals that are at least min_len and, if given, at most max_len
+    // long
+    #[args(min_len = "0", max_len = "None")]
+    fn sum(&self, py: Python, min_len: Value, max_len: Option<Value>) -> PyResult<u64> {
+        let lengths = LengthFilter::new(min_len, max_len);
+        Ok(py.allow_threads(|| self._impl.sum(lengths)))
+    }
+
+    // Get the number of intervals for an id
+    fn get_interval_count(&self, id: Id) -> PyResult<usize> {
+        Ok(self._impl.get_interval_count(id)?)
+    }
+
+    // Get an interval by index
+    fn get_interval(&self, id: Id, idx: usize) -> PyResult<Interval> {
+        Ok(self._impl.get_interval(id, idx)?)
+    }
+
+    // Get all intervals for an id, or only those whose length is in [min_len, max_len]
+    #[args(min_len = "0", max_len = "None")]
+    fn get_intervals(&self, py: Python, id: Id, use_default: bool, min_len: Value,
+                     max_len: Option<Value>) -> PyResult<Vec<Interval>> {
+        let lengths = LengthFilter::new(min_len, max_len);
+        or_default(
+            py.allow_threads(|| self._impl.get_intervals(id, lengths)), use_default, vec![])
+    }
+
+    // Same as get_intervals, but returns an (n, 2) numpy array, which is a view of the file if
+    // no length filter is given
+    #[args(min_len = "0", max_len = "None")]
+    fn get_intervals_array(&self, py: Python, id: Id, use_default: bool, min_len: Value,
+                           max_len: Option<Value>) -> PyResult<PyObject> {
+        if LengthFilter::new(min_len, max_len).is_any() {
+            or_default(self._impl.get_interval_count(id), use_default, 0)?;
+            return Array::from_set(self._impl.clone(), id).into_numpy(py);
+        }
+        let res = self.get_intervals(py, id, use_default, min_len, max_len)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Get whether a target is in any of the intervals in the set
+    fn is_contained(&self, id: Id, target: Value, use_default: bool) -> PyResult<bool> {
+        or_default(self._impl.is_contained(id, target), use_default, false)
+    }
+
+    // Get whether start and end intersect with any interval in the set
+    fn has_intersection(&self, id: Id, start: Value, end: Value,
+                        use_default: bool) -> PyResult<bool> {
+        or_default(self._impl.has_intersection(id, start, end), use_default, false)
+    }
+
+    // Intersect a sorted list of intervals, either as a list of tuples or an (n, 2) array. Only
+    // the intervals in the set whose length is in [min_len, max_len] are included.
+    #[args(min_len = "0", max_len = "None")]
+    fn intersect(&self, py: Python, id: Id, intervals: &PyAny, use_default: bool,
+                 min_len: Value, max_len: Option<Value>) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        let lengths = LengthFilter::new(min_len, max_len);
+        or_default(
+            py.allow_threads(|| self._impl.intersect(id, &intervals, lengths)),
+            use_default, vec![])
+    }
+
+    // Same as intersect, but returns an (n, 2) numpy array
+    #[args(min_len = "0", max_len = "None")]
+    fn intersect_array(&self, py: Python, id: Id, intervals: &PyAny, use_default: bool,
+                       min_len: Value, max_len: Option<Value>) -> PyResult<PyObject> {
+        let res = self.intersect(py, id, intervals, use_default, min_len, max_len)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Intersect and then sum
+    #[args(min_len = "0", max_len = "None")]
+    fn intersect_sum(&self, py: Python, id: Id, intervals: &PyAny, use_default: bool,
+                     min_len: Value, max_len: Option<Value>) -> PyResult<usize> {
+        let intervals = extract_intervals(intervals)?;
+        let lengths = LengthFilter::new(min_len, max_len);
+        or_default(
+            py.allow_threads(|| self._impl.intersect_sum(id, &intervals, lengths)),
+            use_default, 0)
+    }
+
+    // Minus this from intervals, either as a list of tuples or an (n, 2) array
+    fn minus(&self, py: Python, id: Id, intervals: &PyAny,
+             use_default: bool) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        let res = py.allow_threads(|| self._impl.minus(id, &intervals));
+        or_default(res, use_default, intervals)
+    }
+
+    // Same as minus, but returns an (n, 2) numpy array
+    fn minus_array(&self, py: Python, id: Id, intervals: &PyAny,
+                   use_default: bool) -> PyResult<PyObject> {
+        let res = self.minus(py, id, intervals, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Merge a sorted list of intervals into this, either as a list of tuples or an (n, 2) array
+    fn union(&self, py: Python, id: Id, intervals: &PyAny,
+             use_default: bool) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        let res = py.allow_threads(|| self._impl.union(id, &intervals));
+        or_default(res, use_default, ops::coalesce(intervals.iter().cloned(), 0).collect())
+    }
+
+    // Same as union, but returns an (n, 2) numpy array
+    fn union_array(&self, py: Python, id: Id, intervals: &PyAny,
+                   use_default: bool) -> PyResult<PyObject> {
+        let res = self.union(py, id, intervals, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Merge and then sum
+    fn union_sum(&self, py: Python, id: Id, intervals: &PyAny,
+                 use_default: bool) -> PyResult<u64> {
+        let intervals = extract_intervals(intervals)?;
+        let res = py.allow_threads(|| self._impl.union_sum(id, &intervals));
+        or_default(res, use_default, ops::coalesce(intervals.iter().cloned(), 0).fold(
+            0u64, |acc, int| acc + (int.1 - int.0) as u64))
+    }
+
+    // Get the parts of this and of a sorted list of intervals that are not in both
+    fn symmetric_difference(&self, py: Python, id: Id, intervals: &PyAny,
+                            use_default: bool) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        let res = py.allow_threads(|| self._impl.symmetric_difference(id, &intervals));
+        or_default(res, use_default, ops::coalesce(intervals.iter().cloned(), 0).collect())
+    }
+
+    // Same as symmetric_difference, but returns an (n, 2) numpy array
+    fn symmetric_difference_array(&self, py: Python, id: Id, intervals: &PyAny,
+                                  use_default: bool) -> PyResult<PyObject> {
+        let res = self.symmetric_difference(py, id, intervals, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Minus a sorted list of intervals from this, the converse of minus
+    fn difference_from(&self, py: Python, id: Id, intervals: &PyAny,
+                       use_default: bool) -> PyResult<Vec<Interval>> {
+        let intervals = extract_intervals(intervals)?;
+        or_default(
+            py.allow_threads(|| self._impl.difference_from(id, &intervals)), use_default, vec![])
+    }
+
+    // Same as difference_from, but returns an (n, 2) numpy array
+    fn difference_from_array(&self, py: Python, id: Id, intervals: &PyAny,
+                             use_default: bool) -> PyResult<PyObject> {
+        let res = self.difference_from(py, id, intervals, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Get the parts of [lo, hi) that are not in any interval for an id. hi is either a number
+    // or a table of bounds indexed by id (e.g., the length of each video).
+    fn complement(&self, py: Python, id: Id, lo: Value, hi: &PyAny,
+                  use_default: bool) -> PyResult<Vec<Interval>> {
+        let hi = extract_bound(hi, id)?;
+        let res = py.allow_threads(|| self._impl.complement(id, lo, hi));
+        or_default(res, use_default, if hi > lo { vec![(lo, hi)] } else { vec![] })
+    }
+
+    // Same as complement, but returns an (n, 2) numpy array
+    fn complement_array(&self, py: Python, id: Id, lo: Value, hi: &PyAny,
+                        use_default: bool) -> PyResult<PyObject> {
+        let res = self.complement(py, id, lo, hi, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Complement and then sum
+    fn complement_sum(&self, py: Python, id: Id, lo: Value, hi: &PyAny,
+                      use_default: bool) -> PyResult<u64> {
+        let hi = extract_bound(hi, id)?;
+        let res = py.allow_threads(|| self._impl.complement_sum(id, lo, hi));
+        or_default(res, use_default, hi.saturating_sub(lo) as u64)
+    }
+
+    // Extend each interval by amount at both ends, merging those that then overlap
+    fn dilate(&self, py: Python, id: Id, amount: Value,
+              use_default: bool) -> PyResult<Vec<Interval>> {
+        or_default(py.allow_threads(|| self._impl.dilate(id, amount)), use_default, vec![])
+    }
+
+    // Same as dilate, but returns an (n, 2) numpy array
+    fn dilate_array(&self, py: Python, id: Id, amount: Value,
+                    use_default: bool) -> PyResult<PyObject> {
+        let res = self.dilate(py, id, amount, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Shrink each interval by amount at both ends, dropping those that vanish
+    fn erode(&self, py: Python, id: Id, amount: Value,
+             use_default: bool) -> PyResult<Vec<Interval>> {
+        or_default(py.allow_threads(|| self._impl.erode(id, amount)), use_default, vec![])
+    }
+
+    // Same as erode, but returns an (n, 2) numpy array
+    fn erode_array(&self, py: Python, id: Id, amount: Value,
+                   use_default: bool) -> PyResult<PyObject> {
+        let res = self.erode(py, id, amount, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Merge intervals that are less than gap apart
+    fn coalesce(&self, py: Python, id: Id, gap: Value,
+                use_default: bool) -> PyResult<Vec<Interval>> {
+        or_default(py.allow_threads(|| self._impl.coalesce(id, gap)), use_default, vec![])
+    }
+
+    // Same as coalesce, but returns an (n, 2) numpy array
+    fn coalesce_array(&self, py: Python, id: Id, gap: Value,
+                      use_default: bool) -> PyResult<PyObject> {
+        let res = self.coalesce(py, id, gap, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Remove the parts of intervals that are at most 2 * amount long
+    fn opening(&self, py: Python, id: Id, amount: Value,
+               use_default: bool) -> PyResult<Vec<Interval>> {
+        or_default(py.allow_threads(|| self._impl.opening(id, amount)), use_default, vec![])
+    }
+
+    // Same as opening, but returns an (n, 2) numpy array
+    fn opening_array(&self, py: Python, id: Id, amount: Value,
+                     use_default: bool) -> PyResult<PyObject> {
+        let res = self.opening(py, id, amount, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // Fill the gaps between intervals that are at most 2 * amount long
+    fn closing(&self, py: Python, id: Id, amount: Value,
+               use_default: bool) -> PyResult<Vec<Interval>> {
+        or_default(py.allow_threads(|| self._impl.closing(id, amount)), use_default, vec![])
+    }
+
+    // Same as closing, but returns an (n, 2) numpy array
+    fn closing_array(&self, py: Python, id: Id, amount: Value,
+                     use_default: bool) -> PyResult<PyObject> {
+        let res = self.closing(py, id, amount, use_default)?;
+        Array::from_intervals(&res).into_numpy(py)
+    }
+
+    // In strict mode, check that the intervals for every id are sorted and non-overlapping.
+    // Duplicate ids are handled according to duplicates: "error", "first", "last" (the
+    // default) or "merge".
+    #[new]
+    #[args(strict = "false", duplicates = "\"last\"")]
+    fn new(py: Python, data_file: String, strict: bool, duplicates: &str) -> PyResult<Self> {
+        let duplicates = parse_duplicates(duplicates)?;
+        let mapping = py.allow_threads(
+            || MmapIntervalSetMapping::open(&data_file, strict, duplicates))?;
+        Ok(PyMmapIntervalSetMapping {_impl: Arc::new(mapping)})
+    }
+}
@@ -0,0 +1,89 @@
+/*
+* Python bindings, built with the python feature
+*
+* Thin wrappers around the Rust types that convert arguments and errors.
+*/
+
+mod array;
+mod combine;
+mod convert;
+mod expr;
+mod isetmap;
+mod ilistmap;
+mod payload;
+mod query;
+mod verify;
+mod writer;
+
+use pyo3::prelude::*;
+use pyo3::exceptions;
+
+use combine::{ListUnionMapping, SetIntersectionMapping, SetUnionMapping};
+use error::{Error, Result};
+use expr::Expr;
+use ilistmap::MmapIntervalListMapping;
+use isetmap::MmapIntervalSetMapping;
+use payload::PayloadPredicate;
+use self::combine::*;
+use self::expr::PyExpr;
+use self::isetmap::PyMmapIntervalSetMapping;
+use self::ilistmap::PyMmapIntervalListMapping;
+use self::payload::PyPayloadPredicate;
+use self::query::PyQuery;
+use self::writer::{PyIntervalSetMappingWriter, PyIntervalListMappingWriter};
+
+// The mappings are immutable once opened, so the wrappers can be shared between threads and
+// queries can run without holding the GIL
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<MmapIntervalSetMapping>();
+    assert_send_sync::<MmapIntervalListMapping>();
+    assert_send_sync::<ListUnionMapping>();
+    assert_send_sync::<SetUnionMapping>();
+    assert_send_sync::<SetIntersectionMapping>();
+    assert_send_sync::<Expr>();
+    assert_send_sync::<PayloadPredicate>();
+};
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> PyErr {
+        match e {
+            Error::IdNotFound(_) | Error::IndexOutOfRange(_) =>
+                exceptions::IndexError::py_err(e.to_string()),
+            _ => exceptions::Exception::py_err(e.to_string())
+        }
+    }
+}
+
+// Return default instead of raising for a missing id if use_default is set
+fn or_default<T>(res: Result<T>, use_default: bool, default: T) -> PyResult<T> {
+    match res {
+        Err(Error::IdNotFound(_)) if use_default => Ok(default),
+        res => res.map_err(PyErr::from)
+    }
+}
+
+#[pymodule]
+fn rs_intervalset(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+
+    #[pyfn(m, "verify", kind = "None", payload_len = "None", max_problems = "1000")]
+    fn verify_py(
+        py: Python, path: String, kind: Option<&str>, payload_len: Option<usize>,
+        max_problems: usize
+    ) -> PyResult<PyObject> {
+        verify::verify_py(py, path, kind, payload_len, max_problems)
+    }
+
+    m.add_class::<PyMmapIntervalSetMapping>()?;
+    m.add_class::<PyMmapIntervalListMapping>()?;
+    m.add_class::<PyCombinedMapping>()?;
+    m.add_class::<PyListUnionMapping>()?;
+    m.add_class::<PySetUnionMapping>()?;
+    m.add_class::<PySetIntersectionMapping>()?;
+    m.add_class::<PyPayloadPredicate>()?;
+    m.add_class::<PyExpr>()?;
+    m.add_class::<PyQuery>()?;
+    m.add_class::<PyIntervalSetMappingWriter>()?;
+    m.add_class::<PyIntervalListMappingWriter>()?;
+    Ok(())
+}
@@ -0,0 +1,114 @@
+/*
+* Python wrapper for payload predicates
+*
+* Predicates are combined with &, | and ~, and are accepted in place of a payload mask by the
+* list mapping methods, in which case the payload value must be None.
+*/
+
+use pyo3::prelude::*;
+use pyo3::class::{PyNumberProtocol, PyObjectProtocol};
+use pyo3::exceptions;
+use pyo3::types::PyAny;
+use std::sync::Arc;
+
+use common::*;
+use payload::PayloadPredicate;
+
+#[pyclass(name=PayloadPredicate)]
+pub struct PyPayloadPredicate {
+    pub(crate) _impl: Arc<PayloadPredicate>
+}
+
+impl PyPayloadPredicate {
+
+    fn new(predicate: PayloadPredicate) -> Self {
+        PyPayloadPredicate {_impl: Arc::new(predicate)}
+    }
+
+    fn extract(obj: &PyAny) -> PyResult<Arc<PayloadPredicate>> {
+        match obj.extract::<PyRef<PyPayloadPredicate>>() {
+            Ok(p) => Ok(p._impl.clone()),
+            Err(_) => Err(exceptions::TypeError::py_err("expected a PayloadPredicate"))
+        }
+    }
+}
+
+#[pymethods]
+impl PyPayloadPredicate {
+
+    // payload & mask == value
+    #[staticmethod]
+    fn equals(mask: Payload, value: Payload) -> PyResult<Self> {
+        Ok(PyPayloadPredicate::new(PayloadPredicate::equals(mask, value)))
+    }
+
+    // payload & mask is one of values
+    #[staticmethod]
+    fn one_of(mask: Payload, values: Vec<Payload>) -> PyResult<Self> {
+        Ok(PyPayloadPredicate::new(PayloadPredicate::one_of(mask, values)))
+    }
+
+    // min <= payload & mask <= max. The masked payload is not shifted, so the bounds are in
+    // the same bit positions as the mask.
+    #[staticmethod]
+    fn range(mask: Payload, min: Payload, max: Payload) -> PyResult<Self> {
+        Ok(PyPayloadPredicate::new(PayloadPredicate::range(mask, min, max)))
+    }
+
+    fn matches(&self, payload: Payload) -> PyResult<bool> {
+        Ok(self._impl.matches(payload))
+    }
+}
+
+#[pyproto]
+impl<'p> PyNumberProtocol<'p> for PyPayloadPredicate {
+    fn __and__(lhs: &'p PyAny, rhs: &'p PyAny) -> PyResult<PyPayloadPredicate> {
+        Ok(PyPayloadPredicate::new(PayloadPredicate::and(
+            PyPayloadPredicate::extract(lhs)?, PyPayloadPredicate::extract(rhs)?)))
+    }
+
+    fn __or__(lhs: &'p PyAny, rhs: &'p PyAny) -> PyResult<PyPayloadPredicate> {
+        Ok(PyPayloadPredicate::new(PayloadPredicate::or(
+            PyPayloadPredicate::extract(lhs)?, PyPayloadPredicate::extract(rhs)?)))
+    }
+
+    fn __invert__(&'p self) -> PyResult<PyPayloadPredicate> {
+        Ok(PyPayloadPredicate::new(PayloadPredicate::not(self._impl.clone())))
+    }
+}
+
+#[pyproto]
+impl<'p> PyObjectProtocol<'p> for PyPayloadPredicate {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("PayloadPredicate({})", self._impl))
+    }
+}
+
+// The predicate for a payload mask and value, where the mask may instead be a PayloadPredicate
+// and the value is then None
+pub fn extract_payload(mask: &PyAny, value: Option<Payload>) -> PyResult<PayloadPredicate> {
+    if let Ok(p) = mask.extract::<PyRef<PyPayloadPredicate>>() {
+        return match value {
+            None => Ok((*p._impl).clone()),
+            Some(_) => Err(exceptions::TypeError::py_err(
+                "payload_value must be None when payload_mask is a PayloadPredicate"))
+        };
+    }
+    match value {
+        Some(value) => Ok(PayloadPredicate::equals(mask.extract()?, value)),
+        None => Err(exceptions::TypeError::py_err(
+            "payload_value is required when payload_mask is not a PayloadPredicate"))
+    }
+}
+
+// Same as extract_payload, where the mask and value both default to 0, matching every payload
+pub fn extract_optional_payload(
+    mask: Option<&PyAny>, value: Option<Payload>
+) -> PyResult<PayloadPredicate> {
+    match mask {
+        Some(mask) if mask.extract::<PyRef<PyPayloadPredicate>>().is_ok() =>
+            extract_payload(mask, value),
+        Some(mask) => Ok(PayloadPredicate::equals(mask.extract()?, value.unwrap_or(0))),
+        None => Ok(PayloadPredicate::equals(0, value.unwrap_or(0)))
+    }
+}
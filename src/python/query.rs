@@ -0,0 +1,64 @@
+/*
+* Python wrapper for the query language
+*/
+
+use pyo3::prelude::*;
+use pyo3::class::PyObjectProtocol;
+use pyo3::exceptions;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+
+use python::expr::PyExpr;
+use query::{ParseError, Query};
+
+#[pyclass(name=Query)]
+pub struct PyQuery {
+    _impl: Query,
+}
+
+impl PyQuery {
+
+    fn to_py_err(&self, e: ParseError) -> PyErr {
+        exceptions::ValueError::py_err(e.describe(self._impl.text()))
+    }
+}
+
+#[pymethods]
+impl PyQuery {
+
+    // The names used in the query, sorted
+    fn names(&self) -> PyResult<Vec<&str>> {
+        Ok(self._impl.names())
+    }
+
+    // The plan as an indented tree, one operation per line
+    fn explain(&self) -> PyResult<String> {
+        Ok(self._impl.explain())
+    }
+
+    // Build an Expr, looking up the names in a dict of mappings and expressions
+    fn compile(&self, py: Python, bindings: &PyDict) -> PyResult<PyObject> {
+        let mut resolved = HashMap::new();
+        for name in self._impl.names() {
+            if let Some(obj) = bindings.get_item(name) {
+                resolved.insert(name.to_string(), PyExpr::binding(obj)?);
+            }
+        }
+        let expr = self._impl.compile(&resolved).map_err(|e| self.to_py_err(e))?;
+        PyExpr::wrap(py, expr)
+    }
+
+    #[new]
+    fn new(query: &str) -> PyResult<Self> {
+        let parsed = Query::parse(query).map_err(
+            |e| exceptions::ValueError::py_err(e.describe(query)))?;
+        Ok(PyQuery {_impl: parsed})
+    }
+}
+
+#[pyproto]
+impl<'p> PyObjectProtocol<'p> for PyQuery {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("Query({:?})", self._impl.text()))
+    }
+}
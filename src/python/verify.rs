@@ -0,0 +1,55 @@
+/*
+* Python wrapper for verify_file
+*/
+
+use pyo3::prelude::*;
+use pyo3::exceptions;
+use pyo3::types::{PyDict, PyList};
+use std::io;
+
+use common::*;
+use verify::verify_file;
+
+fn kind_from_str(kind: Option<&str>) -> PyResult<Option<Kind>> {
+    match kind {
+        None => Ok(None),
+        Some("set") => Ok(Some(Kind::Set)),
+        Some("list") => Ok(Some(Kind::List)),
+        Some(kind) => Err(exceptions::ValueError::py_err(format!("unknown kind: {}", kind)))
+    }
+}
+
+// Check a file and return a report as a dict. The kind is either "set" or "list", and is only
+// required for files without a header.
+pub fn verify_py(
+    py: Python, path: String, kind: Option<&str>, payload_len: Option<usize>,
+    max_problems: usize
+) -> PyResult<PyObject> {
+    let report = verify_file(&path, kind_from_str(kind)?, payload_len, max_problems).map_err(
+        |e| match e.kind() {
+            io::ErrorKind::InvalidInput => exceptions::ValueError::py_err(e.to_string()),
+            _ => exceptions::Exception::py_err(e.to_string())
+        })?;
+    let problems = PyList::empty(py);
+    for problem in &report.problems {
+        let p = PyDict::new(py);
+        p.set_item("offset", problem.offset)?;
+        p.set_item("id", problem.id)?;
+        p.set_item("index", problem.index)?;
+        p.set_item("kind", problem.kind.name())?;
+        p.set_item("error", problem.kind.is_error())?;
+        p.set_item("message", &problem.message)?;
+        problems.append(p)?;
+    }
+    let res = PyDict::new(py);
+    res.set_item("ok", report.is_ok())?;
+    res.set_item("kind", match report.kind { Kind::Set => "set", Kind::List => "list" })?;
+    res.set_item("payload_len", report.payload_len)?;
+    res.set_item("has_header", report.has_header)?;
+    res.set_item("has_index", report.has_index)?;
+    res.set_item("id_count", report.id_count)?;
+    res.set_item("interval_count", report.interval_count)?;
+    res.set_item("problems", problems)?;
+    res.set_item("problems_truncated", report.problems_truncated)?;
+    Ok(res.into())
+}
@@ -0,0 +1,96 @@
+/*
+* Python wrappers for the writers
+*/
+
+use pyo3::prelude::*;
+use pyo3::exceptions;
+use pyo3::types::PyAny;
+use std::io;
+
+use common::*;
+use python::convert::{extract_intervals, extract_intervals_with_payload};
+use writer::{IntervalSetMappingWriter, IntervalListMappingWriter};
+
+fn to_py_err(e: io::Error) -> PyErr {
+    match e.kind() {
+        io::ErrorKind::InvalidInput => exceptions::AssertionError::py_err(e.to_string()),
+        io::ErrorKind::InvalidData => exceptions::ValueError::py_err(e.to_string()),
+        _ => exceptions::Exception::py_err(e.to_string())
+    }
+}
+
+#[pyclass(name=IntervalSetMappingWriter)]
+pub struct PyIntervalSetMappingWriter {
+    _impl: IntervalSetMappingWriter,
+}
+
+#[pymethods]
+impl PyIntervalSetMappingWriter {
+
+    #[new]
+    #[args(append = "false", index = "false", header = "false", repair = "false")]
+    fn new(
+        path: String, append: bool, index: bool, header: bool, repair: bool
+    ) -> PyResult<Self> {
+        Ok(PyIntervalSetMappingWriter {
+            _impl: IntervalSetMappingWriter::new(
+                &path, append, index, header, repair).map_err(to_py_err)?
+        })
+    }
+
+    // Write the intervals for an id, either as a list of tuples or an (n, 2) array
+    fn write(&mut self, id: Id, intervals: &PyAny) -> PyResult<()> {
+        let intervals = extract_intervals(intervals)?;
+        self._impl.write(id, &intervals).map_err(to_py_err)
+    }
+
+    fn close(&mut self) -> PyResult<()> {
+        self._impl.close().map_err(to_py_err)
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, _type: &PyAny, _value: &PyAny, _tb: &PyAny) -> PyResult<()> {
+        self.close()
+    }
+}
+
+#[pyclass(name=IntervalListMappingWriter)]
+pub struct PyIntervalListMappingWriter {
+    _impl: IntervalListMappingWriter,
+}
+
+#[pymethods]
+impl PyIntervalListMappingWriter {
+
+    #[new]
+    #[args(append = "false", index = "false", header = "false")]
+    fn new(
+        path: String, payload_len: usize, append: bool, index: bool, header: bool
+    ) -> PyResult<Self> {
+        Ok(PyIntervalListMappingWriter {
+            _impl: IntervalListMappingWriter::new(
+                &path, payload_len, append, index, header).map_err(to_py_err)?
+        })
+    }
+
+    // Write the intervals for an id, either as a list of tuples or an (n, 3) array
+    fn write(&mut self, id: Id, intervals: &PyAny) -> PyResult<()> {
+        let intervals = extract_intervals_with_payload(intervals)?;
+        self._impl.write(id, &intervals).map_err(to_py_err)
+    }
+
+    fn close(&mut self) -> PyResult<()> {
+        self._impl.close().map_err(to_py_err)
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, _type: &PyAny, _value: &PyAny, _tb: &PyAny) -> PyResult<()> {
+        self.close()
+    }
+}
@@ -0,0 +1,487 @@
+/*
+* Query language
+*
+* A small text syntax for expressions over named mappings, e.g.,
+*
+*     faces[mask=0xff,value=3] & captions - commercials | dilate(1000)
+*
+* From lowest to highest precedence, the operators are | (union), - (difference) and &
+* (intersection); all are left associative. A list mapping can be filtered by payload with
+* [mask=..,value=..], by a set of values with [mask=..,in=(..,..)], or by an inclusive range
+* with [mask=..,min=..,max=..]. The transforms dilate(n), erode(n), coalesce(n) and
+* filter_length(min[, max]) apply to everything to their left when they follow a |, or can be
+* called with an expression as their first argument, e.g., dilate(faces & captions, 1000).
+* Numbers are decimal or hexadecimal (0x...).
+*
+* A query is parsed once, and compiled into an Expr by binding its names to mappings.
+*/
+
+use std::collections::{BTreeSet, HashMap};
+use std::error;
+use std::fmt;
+use std::result;
+use std::sync::Arc;
+
+use combine::CombinedMapping;
+use common::*;
+use expr::Expr;
+use ilistmap::MmapIntervalListMapping;
+use isetmap::MmapIntervalSetMapping;
+use payload::PayloadPredicate;
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,    // Offset in the query, in characters
+}
+
+impl ParseError {
+
+    fn new<S: Into<String>>(message: S, position: usize) -> Self {
+        ParseError {message: message.into(), position}
+    }
+
+    // The message followed by the query, with the position marked
+    pub fn describe(&self, query: &str) -> String {
+        format!("{} at position {}\n  {}\n  {}^", self.message, self.position, query,
+                " ".repeat(self.position))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl error::Error for ParseError {}
+
+pub type ParseResult<T> = result::Result<T, ParseError>;
+
+// What a name in a query refers to
+pub enum Binding {
+    Set(Arc<MmapIntervalSetMapping>),
+    List(Arc<MmapIntervalListMapping>),
+    Expr(Arc<Expr>),
+    Mapping(Arc<dyn CombinedMapping + Send + Sync>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transform {
+    Dilate(Value),
+    Erode(Value),
+    Coalesce(Value),
+    FilterLength(Value, Option<Value>),
+}
+
+const TRANSFORMS: &[&str] = &["dilate", "erode", "coalesce", "filter_length"];
+
+#[derive(Debug, PartialEq)]
+enum Ast {
+    Name {name: String, position: usize, filter: Option<PayloadPredicate>},
+    And(Box<Ast>, Box<Ast>),
+    Or(Box<Ast>, Box<Ast>),
+    Minus(Box<Ast>, Box<Ast>),
+    Transform(Box<Ast>, Transform),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Number(u64),
+    Symbol(char),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Symbol(c) => write!(f, "'{}'", c),
+            Token::End => write!(f, "end of query"),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> ParseResult<Vec<(Token, usize)>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_'
+                                      || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push((Token::Name(chars[start..i].iter().collect()), start));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let parsed = if text.starts_with("0x") || text.starts_with("0X") {
+                u64::from_str_radix(&text[2..], 16)
+            } else {
+                text.parse()
+            };
+            match parsed {
+                Ok(n) => tokens.push((Token::Number(n), start)),
+                Err(_) => return Err(ParseError::new(format!("invalid number '{}'", text), start))
+            }
+        } else if "&|-()[],=".contains(c) {
+            tokens.push((Token::Symbol(c), start));
+            i += 1;
+        } else {
+            return Err(ParseError::new(format!("unexpected character '{}'", c), start));
+        }
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, expected: &str) -> ParseResult<T> {
+        Err(ParseError::new(
+            format!("expected {}, found {}", expected, self.peek()), self.position()))
+    }
+
+    fn expect(&mut self, c: char) -> ParseResult<()> {
+        if *self.peek() == Token::Symbol(c) {
+            self.next();
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", c))
+        }
+    }
+
+    // Consume a name, if the next token is one
+    fn next_name(&mut self) -> Option<(String, usize)> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                let position = self.position();
+                self.next();
+                Some((name, position))
+            },
+            _ => None
+        }
+    }
+
+    fn is_transform_call(&self) -> bool {
+        match (self.peek(), self.tokens.get(self.pos + 1)) {
+            (Token::Name(name), Some((Token::Symbol('('), _))) =>
+                TRANSFORMS.contains(&name.as_str()),
+            _ => false
+        }
+    }
+
+    // union := difference ('|' (transform | difference))*
+    fn parse_union(&mut self) -> ParseResult<Ast> {
+        let mut ast = self.parse_difference()?;
+        while *self.peek() == Token::Symbol('|') {
+            self.next();
+            if self.is_transform_call() {
+                let (name, position) = self.next_name().unwrap();
+                self.expect('(')?;
+                let transform = self.parse_transform_args(&name, position)?;
+                ast = Ast::Transform(Box::new(ast), transform);
+            } else {
+                ast = Ast::Or(Box::new(ast), Box::new(self.parse_difference()?));
+            }
+        }
+        Ok(ast)
+    }
+
+    // difference := intersection ('-' intersection)*
+    fn parse_difference(&mut self) -> ParseResult<Ast> {
+        let mut ast = self.parse_intersection()?;
+        while *self.peek() == Token::Symbol('-') {
+            self.next();
+            ast = Ast::Minus(Box::new(ast), Box::new(self.parse_intersection()?));
+        }
+        Ok(ast)
+    }
+
+    // intersection := primary ('&' primary)*
+    fn parse_intersection(&mut self) -> ParseResult<Ast> {
+        let mut ast = self.parse_primary()?;
+        while *self.peek() == Token::Symbol('&') {
+            self.next();
+            ast = Ast::And(Box::new(ast), Box::new(self.parse_primary()?));
+        }
+        Ok(ast)
+    }
+
+    // primary := '(' union ')' | transform '(' union ',' args ')' | name filter?
+    fn parse_primary(&mut self) -> ParseResult<Ast> {
+        if *self.peek() == Token::Symbol('(') {
+            self.next();
+            let ast = self.parse_union()?;
+            self.expect(')')?;
+            return Ok(ast);
+        }
+        if self.is_transform_call() {
+            let (name, position) = self.next_name().unwrap();
+            self.expect('(')?;
+            let ast = self.parse_union()?;
+            self.expect(',')?;
+            let transform = self.parse_transform_args(&name, position)?;
+            return Ok(Ast::Transform(Box::new(ast), transform));
+        }
+        let (name, position) = match self.next_name() {
+            Some(name) => name,
+            None => return self.error("a name or '('")
+        };
+        let filter = if *self.peek() == Token::Symbol('[') {
+            self.next();
+            Some(self.parse_filter()?)
+        } else {
+            None
+        };
+        Ok(Ast::Name {name, position, filter})
+    }
+
+    // filter := (('mask' | 'value' | 'min' | 'max') '=' number | 'in' '=' numbers),+ ']'
+    fn parse_filter(&mut self) -> ParseResult<PayloadPredicate> {
+        let mut mask = None;
+        let mut value = None;
+        let mut values = None;
+        let mut min = None;
+        let mut max = None;
+        // The first key that chose between a value, a set of values and a range
+        let mut kind: Option<String> = None;
+        loop {
+            let (key, position) = match self.next_name() {
+                Some(key) => key,
+                None => return self.error("'mask', 'value', 'in', 'min' or 'max'")
+            };
+            self.expect('=')?;
+            let given = match key.as_str() {
+                "mask" => mask.replace(self.parse_number()?).is_some(),
+                "value" => value.replace(self.parse_number()?).is_some(),
+                "in" => values.replace(self.parse_numbers()?).is_some(),
+                "min" => min.replace(self.parse_number()?).is_some(),
+                "max" => max.replace(self.parse_number()?).is_some(),
+                _ => return Err(ParseError::new(format!(
+                    "unknown filter '{}', expected 'mask', 'value', 'in', 'min' or 'max'", key),
+                    position))
+            };
+            if given {
+                return Err(ParseError::new(format!("'{}' is given twice", key), position));
+            }
+            let is_range = |k: &str| k == "min" || k == "max";
+            match kind {
+                _ if key == "mask" => (),
+                None => kind = Some(key),
+                Some(ref first) if is_range(first) && is_range(&key) => (),
+                Some(ref first) => return Err(ParseError::new(
+                    format!("'{}' cannot be combined with '{}'", key, first), position)),
+            }
+            if *self.peek() == Token::Symbol(',') {
+                self.next();
+            } else {
+                self.expect(']')?;
+                break;
+            }
+        }
+        let mask = mask.unwrap_or(!0);
+        Ok(match values {
+            Some(values) => PayloadPredicate::one_of(mask, values),
+            None if min.is_some() || max.is_some() =>
+                PayloadPredicate::range(mask, min.unwrap_or(0), max.unwrap_or(!0)),
+            None => PayloadPredicate::equals(mask, value.unwrap_or(0)),
+        })
+    }
+
+    // numbers := '(' number (',' number)* ')'
+    fn parse_numbers(&mut self) -> ParseResult<Vec<u64>> {
+        self.expect('(')?;
+        let mut numbers = vec![self.parse_number()?];
+        while *self.peek() == Token::Symbol(',') {
+            self.next();
+            numbers.push(self.parse_number()?);
+        }
+        self.expect(')')?;
+        Ok(numbers)
+    }
+
+    fn parse_number(&mut self) -> ParseResult<u64> {
+        match *self.peek() {
+            Token::Number(n) => {
+                self.next();
+                Ok(n)
+            },
+            _ => self.error("a number")
+        }
+    }
+
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        let position = self.position();
+        let n = self.parse_number()?;
+        if n > Value::MAX as u64 {
+            return Err(ParseError::new(format!("{} is out of range", n), position));
+        }
+        Ok(n as Value)
+    }
+
+    // Parse the numbers after the opening parenthesis of a transform, up to the closing one
+    fn parse_transform_args(&mut self, name: &str, position: usize) -> ParseResult<Transform> {
+        let mut args = vec![self.parse_value()?];
+        while *self.peek() == Token::Symbol(',') {
+            self.next();
+            args.push(self.parse_value()?);
+        }
+        self.expect(')')?;
+        Ok(match (name, args.as_slice()) {
+            ("dilate", &[n]) => Transform::Dilate(n),
+            ("erode", &[n]) => Transform::Erode(n),
+            ("coalesce", &[n]) => Transform::Coalesce(n),
+            ("filter_length", &[min_len]) => Transform::FilterLength(min_len, None),
+            ("filter_length", &[min_len, max_len]) =>
+                Transform::FilterLength(min_len, Some(max_len)),
+            ("filter_length", _) => return Err(ParseError::new(
+                "filter_length takes a minimum and an optional maximum length", position)),
+            _ => return Err(ParseError::new(format!("{} takes one number", name), position))
+        })
+    }
+}
+
+pub struct Query {
+    text: String,
+    root: Ast,
+}
+
+impl Query {
+
+    pub fn parse(query: &str) -> ParseResult<Self> {
+        let mut parser = Parser {tokens: tokenize(query)?, pos: 0};
+        let root = parser.parse_union()?;
+        if *parser.peek() != Token::End {
+            return parser.error("an operator");
+        }
+        Ok(Query {text: query.to_string(), root})
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // The names used in the query, sorted
+    pub fn names(&self) -> Vec<&str> {
+        fn visit<'a>(ast: &'a Ast, names: &mut BTreeSet<&'a str>) {
+            match ast {
+                Ast::Name {name, ..} => {
+                    names.insert(name);
+                },
+                Ast::And(a, b) | Ast::Or(a, b) | Ast::Minus(a, b) => {
+                    visit(a, names);
+                    visit(b, names);
+                },
+                Ast::Transform(a, _) => visit(a, names),
+            }
+        }
+        let mut names = BTreeSet::new();
+        visit(&self.root, &mut names);
+        names.into_iter().collect()
+    }
+
+    // Build the expression, looking up the names in bindings
+    pub fn compile(&self, bindings: &HashMap<String, Binding>) -> ParseResult<Arc<Expr>> {
+        Query::compile_ast(&self.root, bindings)
+    }
+
+    fn compile_ast(ast: &Ast, bindings: &HashMap<String, Binding>) -> ParseResult<Arc<Expr>> {
+        Ok(Arc::new(match ast {
+            Ast::Name {name, position, filter} => match (bindings.get(name), filter) {
+                (None, _) => return Err(ParseError::new(
+                    format!("unknown name '{}'", name), *position)),
+                (Some(Binding::List(m)), Some(payload)) => Expr::list(m.clone(), payload.clone()),
+                (Some(Binding::List(m)), None) => Expr::list(m.clone(), PayloadPredicate::any()),
+                (Some(_), Some(_)) => return Err(ParseError::new(
+                    format!("'{}' is not a list mapping and cannot be filtered", name),
+                    *position)),
+                (Some(Binding::Set(m)), None) => Expr::set(m.clone()),
+                (Some(Binding::Expr(e)), None) => return Ok(e.clone()),
+                (Some(Binding::Mapping(m)), None) => Expr::mapping(m.clone()),
+            },
+            Ast::And(a, b) => Expr::and(
+                Query::compile_ast(a, bindings)?, Query::compile_ast(b, bindings)?),
+            Ast::Or(a, b) => Expr::or(
+                Query::compile_ast(a, bindings)?, Query::compile_ast(b, bindings)?),
+            Ast::Minus(a, b) => Expr::minus(
+                Query::compile_ast(a, bindings)?, Query::compile_ast(b, bindings)?),
+            Ast::Transform(a, transform) => {
+                let e = Query::compile_ast(a, bindings)?;
+                match *transform {
+                    Transform::Dilate(n) => Expr::dilate(e, n),
+                    Transform::Erode(n) => Expr::erode(e, n),
+                    Transform::Coalesce(n) => Expr::coalesce(e, n),
+                    Transform::FilterLength(min_len, max_len) =>
+                        Expr::filter_length(e, min_len, max_len),
+                }
+            }
+        }))
+    }
+
+    // The plan as an indented tree, one operation per line
+    pub fn explain(&self) -> String {
+        fn visit(ast: &Ast, depth: usize, out: &mut String) {
+            out.push_str(&"  ".repeat(depth));
+            match ast {
+                Ast::Name {name, filter: None, ..} => out.push_str(name),
+                Ast::Name {name, filter: Some(payload), ..} =>
+                    out.push_str(&format!("{} [{}]", name, payload)),
+                Ast::And(..) => out.push_str("Intersect"),
+                Ast::Or(..) => out.push_str("Union"),
+                Ast::Minus(..) => out.push_str("Minus"),
+                Ast::Transform(_, Transform::Dilate(n)) => out.push_str(&format!("Dilate {}", n)),
+                Ast::Transform(_, Transform::Erode(n)) => out.push_str(&format!("Erode {}", n)),
+                Ast::Transform(_, Transform::Coalesce(n)) =>
+                    out.push_str(&format!("Coalesce {}", n)),
+                Ast::Transform(_, Transform::FilterLength(min_len, None)) =>
+                    out.push_str(&format!("FilterLength >= {}", min_len)),
+                Ast::Transform(_, Transform::FilterLength(min_len, Some(max_len))) =>
+                    out.push_str(&format!("FilterLength {}..={}", min_len, max_len)),
+            }
+            out.push('\n');
+            match ast {
+                Ast::Name {..} => (),
+                Ast::And(a, b) | Ast::Or(a, b) | Ast::Minus(a, b) => {
+                    visit(a, depth + 1, out);
+                    visit(b, depth + 1, out);
+                },
+                Ast::Transform(a, _) => visit(a, depth + 1, out),
+            }
+        }
+        let mut out = String::new();
+        visit(&self.root, 0, &mut out);
+        out
+    }
+}
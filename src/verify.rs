@@ -0,0 +1,249 @@
+/*
+* Integrity checks for IntervalSetMapping and IntervalListMapping files
+*
+* Walk every record in a file and report each problem with the byte offset at which it occurs,
+* instead of failing on the first one like the mappings do.
+*/
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::mem;
+use memmap::{MmapOptions, Mmap};
+
+use common::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProblemKind {
+    Header,
+    Index,
+    TruncatedRecord,
+    DuplicateId,
+    UnsortedId,
+    EmptyInterval,
+    InvertedInterval,
+    UnsortedInterval,
+    OverlappingInterval,
+}
+
+impl ProblemKind {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProblemKind::Header => "header",
+            ProblemKind::Index => "index",
+            ProblemKind::TruncatedRecord => "truncated_record",
+            ProblemKind::DuplicateId => "duplicate_id",
+            ProblemKind::UnsortedId => "unsorted_id",
+            ProblemKind::EmptyInterval => "empty_interval",
+            ProblemKind::InvertedInterval => "inverted_interval",
+            ProblemKind::UnsortedInterval => "unsorted_interval",
+            ProblemKind::OverlappingInterval => "overlapping_interval",
+        }
+    }
+
+    // Duplicate and out of order ids are allowed by the format, but are worth knowing about
+    pub fn is_error(self) -> bool {
+        !matches!(self, ProblemKind::DuplicateId | ProblemKind::UnsortedId)
+    }
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub offset: usize,
+    pub id: Option<Id>,
+    pub index: Option<usize>,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub kind: Kind,
+    pub payload_len: usize,
+    pub has_header: bool,
+    pub has_index: bool,
+    pub id_count: usize,
+    pub interval_count: usize,
+    pub problems: Vec<Problem>,
+    pub problems_truncated: bool,   // Set if more than max_problems were found
+}
+
+impl Report {
+
+    pub fn is_ok(&self) -> bool {
+        !self.problems_truncated && self.problems.iter().all(|p| !p.kind.is_error())
+    }
+
+    fn add(
+        &mut self, max_problems: usize, offset: usize, id: Option<Id>, index: Option<usize>,
+        kind: ProblemKind, message: String
+    ) {
+        if self.problems.len() < max_problems {
+            self.problems.push(Problem {offset, id, index, kind, message});
+        } else {
+            self.problems_truncated = true;
+        }
+    }
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+// Check a file, taking the kind and payload length from its header if they are not given
+pub fn verify_file(
+    path: &str, kind: Option<Kind>, payload_len: Option<usize>, max_problems: usize
+) -> io::Result<Report> {
+    let fh = File::open(path)?;
+    let length = fh.metadata()?.len() as usize;
+    let m = if length > 0 { Some(unsafe { MmapOptions::new().map(&fh)? }) } else { None };
+
+    let file_kind = m.as_ref().and_then(header_kind);
+    let kind = match kind.or(file_kind) {
+        Some(kind) => kind,
+        None => return Err(invalid_input("kind is required for files without a header"))
+    };
+    let payload_len = if kind == Kind::Set { payload_len.or(Some(0)) } else { payload_len };
+    let mut report = Report {
+        kind, payload_len: payload_len.unwrap_or(0), has_header: file_kind.is_some(),
+        has_index: false, id_count: 0, interval_count: 0, problems: Vec::new(),
+        problems_truncated: false
+    };
+    let m = match m {
+        Some(m) => m,
+        None => return Ok(report)
+    };
+
+    if payload_len.is_none() && !has_header_magic(&m) {
+        return Err(invalid_input("payload_len is required for files without a header"));
+    }
+    let begin = match parse_header(&m, kind, payload_len) {
+        Ok((begin, payload_len)) => {
+            report.payload_len = payload_len;
+            begin
+        },
+        Err(msg) => {
+            report.add(max_problems, 0, None, None, ProblemKind::Header, msg);
+            return Ok(report);
+        }
+    };
+    let index = verify_index_trailer(&m, begin, &mut report, max_problems);
+    let end = index.map_or(m.len(), |(base, _)| base);
+    let records = verify_records(&m, begin, end, &mut report, max_problems);
+    if let Some((base, count)) = index {
+        verify_index(&m, base, count, records, &mut report, max_problems);
+    }
+    Ok(report)
+}
+
+// Locate the index entries, if the file has an index
+fn verify_index_trailer(
+    m: &Mmap, begin: usize, report: &mut Report, max_problems: usize
+) -> Option<(usize, usize)> {
+    if m.len() < begin + INDEX_TRAILER_SIZE
+        || &m[m.len() - INDEX_MAGIC.len()..] != INDEX_MAGIC {
+        return None;
+    }
+    report.has_index = true;
+    let index_end = m.len() - INDEX_TRAILER_SIZE;
+    let base = mmap_read_u64(m, index_end) as usize;
+    if base < begin || base > index_end || (index_end - base) % INDEX_ENTRY_SIZE != 0 {
+        report.add(max_problems, index_end, None, None, ProblemKind::Index,
+                   format!("index offset {} is out of range", base));
+        return None;
+    }
+    Some((base, (index_end - base) / INDEX_ENTRY_SIZE))
+}
+
+// Check each record and return the (id, offset of the first interval, count) of each one
+fn verify_records(
+    m: &Mmap, begin: usize, end: usize, report: &mut Report, max_problems: usize
+) -> Vec<(Id, usize, usize)> {
+    let interval_size = INTERVAL_SIZE + report.payload_len;
+    let mut records = Vec::new();
+    let mut seen_ids = HashSet::new();
+    let mut prev_id: Option<Id> = None;
+    let mut i = begin;
+    while i < end {
+        if end - i < mem::size_of::<Id>() + mem::size_of::<u32>() {
+            report.add(max_problems, i, None, None, ProblemKind::TruncatedRecord,
+                       format!("{} trailing bytes", end - i));
+            break;
+        }
+        let id = mmap_read_u32(m, i) as Id;
+        let n = mmap_read_u32(m, i + mem::size_of::<Id>()) as usize;
+        let record_offset = i;
+        i += mem::size_of::<Id>() + mem::size_of::<u32>();
+        if n > (end - i) / interval_size {
+            report.add(max_problems, record_offset, Some(id), None, ProblemKind::TruncatedRecord,
+                       format!("record has {} intervals, but only {} bytes remain", n, end - i));
+            break;
+        }
+        if !seen_ids.insert(id) {
+            report.add(max_problems, record_offset, Some(id), None, ProblemKind::DuplicateId,
+                       format!("id {} appears more than once", id));
+        } else if prev_id.map_or(false, |prev_id| id < prev_id) {
+            report.add(max_problems, record_offset, Some(id), None, ProblemKind::UnsortedId,
+                       format!("id {} follows id {}", id, prev_id.unwrap()));
+        }
+        prev_id = Some(id);
+        records.push((id, i, n));
+
+        let mut prev: Option<Interval> = None;
+        for j in 0..n {
+            let offset = i + j * interval_size;
+            let a = mmap_read_u32(m, offset);
+            let b = mmap_read_u32(m, offset + mem::size_of::<Value>());
+            if a == b {
+                report.add(max_problems, offset, Some(id), Some(j), ProblemKind::EmptyInterval,
+                           format!("empty interval: ({}, {})", a, b));
+            } else if b < a {
+                report.add(max_problems, offset, Some(id), Some(j), ProblemKind::InvertedInterval,
+                           format!("inverted interval: ({}, {})", a, b));
+            }
+            if let Some(prev) = prev {
+                if a < prev.0 {
+                    report.add(max_problems, offset, Some(id), Some(j),
+                               ProblemKind::UnsortedInterval,
+                               format!("interval ({}, {}) starts before ({}, {})",
+                                       a, b, prev.0, prev.1));
+                } else if report.kind == Kind::Set && a < prev.1 {
+                    report.add(max_problems, offset, Some(id), Some(j),
+                               ProblemKind::OverlappingInterval,
+                               format!("interval ({}, {}) overlaps ({}, {})",
+                                       a, b, prev.0, prev.1));
+                }
+            }
+            prev = Some((a, b));
+        }
+        report.interval_count += n;
+        i += n * interval_size;
+    }
+    report.id_count = seen_ids.len();
+    records
+}
+
+// Check that the index lists every record, sorted by id
+fn verify_index(
+    m: &Mmap, base: usize, count: usize, mut records: Vec<(Id, usize, usize)>,
+    report: &mut Report, max_problems: usize
+) {
+    if count != records.len() {
+        report.add(max_problems, base, None, None, ProblemKind::Index,
+                   format!("index has {} entries, but the file has {} records",
+                           count, records.len()));
+    }
+    records.sort_by_key(|record| record.0);
+    for (i, record) in records.iter().enumerate().take(count) {
+        let offset = base + i * INDEX_ENTRY_SIZE;
+        let id = mmap_read_u32(m, offset) as Id;
+        let n = mmap_read_u32(m, offset + mem::size_of::<Id>()) as usize;
+        let interval_offset = mmap_read_u64(m, offset + 2 * mem::size_of::<u32>()) as usize;
+        if (id, interval_offset, n) != *record {
+            report.add(max_problems, offset, Some(id), None, ProblemKind::Index,
+                       format!("index entry {} ({}, {}, {}) does not match record ({}, {}, {})",
+                               i, id, n, interval_offset, record.0, record.2, record.1));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+/*
+* Writers for IntervalSetMapping and IntervalListMapping files
+*
+* Buffered equivalents of the writers in rs_intervalset/writer.py, which produce identical output.
+*/
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use common::*;
+
+type IndexEntry = (Id, u32, u64);
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Writes the records, header and index shared by both formats
+struct RecordWriter {
+    out: Option<BufWriter<File>>,
+    offset: u64,
+    index: Option<Vec<IndexEntry>>,
+}
+
+impl RecordWriter {
+
+    fn open(
+        path: &str, kind: Kind, payload_len: usize, append: bool, index: bool, header: bool
+    ) -> io::Result<Self> {
+        let mut fh = OpenOptions::new()
+            .read(true).write(true).create(true).truncate(!append).open(path)?;
+        let mut index = if index { Some(Vec::new()) } else { None };
+        let length = fh.metadata()?.len();
+        if length > 0 {
+            let has_header = RecordWriter::check_header(
+                &mut fh, length, kind, payload_len, header)?;
+            let records_start = if has_header { HEADER_SIZE as u64 } else { 0 };
+            // Appending to an indexed file keeps it indexed
+            if let Some(entries) = RecordWriter::strip_index(&mut fh, length, records_start)? {
+                index = Some(entries);
+            } else if index.is_some() {
+                // Index the records that are already in the file
+                let interval_size = 2 * mem::size_of::<Value>() + match kind {
+                    Kind::Set => 0,
+                    Kind::List => payload_len
+                };
+                index = Some(RecordWriter::scan_index(&mut fh, length, interval_size)?);
+            }
+        }
+        let offset = fh.seek(SeekFrom::End(0))?;
+        let mut writer = RecordWriter {out: Some(BufWriter::new(fh)), offset, index};
+        if header && offset == 0 {
+            let flags = match kind {
+                Kind::Set => FLAG_SORTED | FLAG_NON_OVERLAPPING,
+                Kind::List => FLAG_SORTED
+            };
+            let mut buf = HEADER_MAGIC.to_vec();
+            buf.write_u16::<LittleEndian>(FORMAT_VERSION)?;
+            buf.extend_from_slice(&[
+                kind as u8, payload_len as u8, mem::size_of::<Value>() as u8, flags, 0, 0]);
+            writer.write_bytes(&buf)?;
+        }
+        Ok(writer)
+    }
+
+    // Check that appending to a file will not change its header, and return whether it has one
+    fn check_header(
+        fh: &mut File, length: u64, kind: Kind, payload_len: usize, header: bool
+    ) -> io::Result<bool> {
+        let mut buf = [0u8; HEADER_SIZE];
+        if length >= HEADER_SIZE as u64 {
+            fh.read_exact(&mut buf)?;
+        }
+        if &buf[..HEADER_MAGIC.len()] == HEADER_MAGIC {
+            if buf[10] != kind as u8 || buf[11] as usize != payload_len {
+                return Err(invalid_data("file has a different header"));
+            }
+            return Ok(true);
+        } else if header {
+            return Err(invalid_data("cannot add a header to a file without one"));
+        }
+        Ok(false)
+    }
+
+    // Remove the index from the end of a file and return its entries. The index has to start
+    // after the header (at records_start) and hold a whole number of entries.
+    fn strip_index(
+        fh: &mut File, length: u64, records_start: u64
+    ) -> io::Result<Option<Vec<IndexEntry>>> {
+        if length < INDEX_TRAILER_SIZE as u64 {
+            return Ok(None);
+        }
+        fh.seek(SeekFrom::Start(length - INDEX_TRAILER_SIZE as u64))?;
+        let index_offset = fh.read_u64::<LittleEndian>()?;
+        let mut magic = [0u8; 8];
+        fh.read_exact(&mut magic)?;
+        if &magic[..] != INDEX_MAGIC {
+            return Ok(None);
+        }
+        let index_end = length - INDEX_TRAILER_SIZE as u64;
+        if index_offset < records_start || index_offset > index_end
+                || (index_end - index_offset) % INDEX_ENTRY_SIZE as u64 != 0 {
+            return Err(invalid_data("file has an invalid index"));
+        }
+        fh.seek(SeekFrom::Start(index_offset))?;
+        let count = (index_end - index_offset) / INDEX_ENTRY_SIZE as u64;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let id = fh.read_u32::<LittleEndian>()?;
+            let n = fh.read_u32::<LittleEndian>()?;
+            entries.push((id, n, fh.read_u64::<LittleEndian>()?));
+        }
+        fh.set_len(index_offset)?;
+        Ok(Some(entries))
+    }
+
+    // Rebuild the index entries of a file without an index from its records
+    fn scan_index(
+        fh: &mut File, length: u64, interval_size: usize
+    ) -> io::Result<Vec<IndexEntry>> {
+        let mut buf = [0u8; HEADER_SIZE];
+        fh.seek(SeekFrom::Start(0))?;
+        if length >= HEADER_SIZE as u64 {
+            fh.read_exact(&mut buf)?;
+        }
+        let mut offset = if &buf[..HEADER_MAGIC.len()] == HEADER_MAGIC { HEADER_SIZE as u64 }
+                         else { 0 };
+        let mut entries = vec![];
+        while offset + 2 * mem::size_of::<u32>() as u64 <= length {
+            fh.seek(SeekFrom::Start(offset))?;
+            let id = fh.read_u32::<LittleEndian>()?;
+            let n = fh.read_u32::<LittleEndian>()?;
+            offset += 2 * mem::size_of::<u32>() as u64;
+            entries.push((id, n, offset));
+            offset += n as u64 * interval_size as u64;
+        }
+        if offset != length {
+            return Err(invalid_data("file ends with a truncated record"));
+        }
+        Ok(entries)
+    }
+
+    fn write_bytes(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.out.as_mut() {
+            Some(out) => {
+                out.write_all(buf)?;
+                self.offset += buf.len() as u64;
+                Ok(())
+            },
+            None => Err(io::Error::new(io::ErrorKind::Other, "writer is closed"))
+        }
+    }
+
+    fn write_record(&mut self, id: Id, n: usize, record: &[u8]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(2 * mem::size_of::<u32>());
+        buf.write_u32::<LittleEndian>(id)?;
+        buf.write_u32::<LittleEndian>(n as u32)?;
+        self.write_bytes(&buf)?;
+        let offset = self.offset;
+        if let Some(index) = self.index.as_mut() {
+            index.push((id, n as u32, offset));
+        }
+        self.write_bytes(record)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if self.out.is_none() {
+            return Ok(());
+        }
+        if let Some(mut index) = self.index.take() {
+            let index_offset = self.offset;
+            index.sort_by_key(|entry| entry.0);
+            let mut buf = Vec::with_capacity(index.len() * INDEX_ENTRY_SIZE + INDEX_TRAILER_SIZE);
+            for (id, n, offset) in index {
+                buf.write_u32::<LittleEndian>(id)?;
+                buf.write_u32::<LittleEndian>(n)?;
+                buf.write_u64::<LittleEndian>(offset)?;
+            }
+            buf.write_u64::<LittleEndian>(index_offset)?;
+            buf.extend_from_slice(INDEX_MAGIC);
+            self.write_bytes(&buf)?;
+        }
+        self.out.take().unwrap().flush()
+    }
+}
+
+impl Drop for RecordWriter {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+fn check_interval(a: Value, b: Value) -> io::Result<()> {
+    if b > a {
+        Ok(())
+    } else {
+        Err(invalid_input(format!("invalid interval: ({}, {})", a, b)))
+    }
+}
+
+pub struct IntervalSetMappingWriter {
+    writer: RecordWriter,
+    repair: bool,
+}
+
+impl IntervalSetMappingWriter {
+
+    // Unsorted or overlapping intervals are rejected, unless repair is set, in which case they
+    // are sorted and merged
+    pub fn new(
+        path: &str, append: bool, index: bool, header: bool, repair: bool
+    ) -> io::Result<Self> {
+        Ok(IntervalSetMappingWriter {
+            writer: RecordWriter::open(path, Kind::Set, 0, append, index, header)?,
+            repair
+        })
+    }
+
+    pub fn write(&mut self, id: Id, intervals: &[Interval]) -> io::Result<()> {
+        if self.repair {
+            for &(a, b) in intervals {
+                check_interval(a, b)?;
+            }
+            let intervals = normalize_set(intervals.to_vec());
+            return self.write_valid(id, &intervals);
+        }
+        if let Some((i, msg)) = find_set_violation(intervals.iter().cloned()) {
+            return Err(invalid_input(format!("id {} interval {}: {}", id, i, msg)));
+        }
+        self.write_valid(id, intervals)
+    }
+
+    fn write_valid(&mut self, id: Id, intervals: &[Interval]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(intervals.len() * INTERVAL_SIZE);
+        for &(a, b) in intervals {
+            buf.write_u32::<LittleEndian>(a)?;
+            buf.write_u32::<LittleEndian>(b)?;
+        }
+        self.writer.write_record(id, intervals.len(), &buf)
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        self.writer.close()
+    }
+}
+
+pub struct IntervalListMappingWriter {
+    writer: RecordWriter,
+    payload_len: usize,
+}
+
+impl IntervalListMappingWriter {
+
+    pub fn new(
+        path: &str, payload_len: usize, append: bool, index: bool, header: bool
+    ) -> io::Result<Self> {
+        if payload_len > mem::size_of::<Payload>() {
+            return Err(invalid_input(format!("invalid payload length: {}", payload_len)));
+        }
+        Ok(IntervalListMappingWriter {
+            writer: RecordWriter::open(path, Kind::List, payload_len, append, index, header)?,
+            payload_len
+        })
+    }
+
+    pub fn write(&mut self, id: Id, intervals: &[(Value, Value, Payload)]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(intervals.len() * (INTERVAL_SIZE + self.payload_len));
+        for &(a, b, c) in intervals {
+            check_interval(a, b)?;
+            if self.payload_len < mem::size_of::<Payload>() && c >> (8 * self.payload_len) != 0 {
+                return Err(invalid_input(format!("payload does not fit: {}", c)));
+            }
+            buf.write_u32::<LittleEndian>(a)?;
+            buf.write_u32::<LittleEndian>(b)?;
+            if self.payload_len > 0 {
+                buf.write_uint::<LittleEndian>(c, self.payload_len)?;
+            }
+        }
+        self.writer.write_record(id, intervals.len(), &buf)
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        self.writer.close()
+    }
+}
@@ -0,0 +1,122 @@
+/*
+* Tests of the Rust API, which do not need the python feature
+*
+* The Python tests cover the queries in depth. These check that the library can be used on its
+* own: writing files, opening them and combining them.
+*/
+
+extern crate rs_intervalset;
+
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::Arc;
+
+use rs_intervalset::{
+    CombinedMapping, Duplicates, Expr, IntervalListMappingWriter, IntervalSetMappingWriter, Kind,
+    LengthFilter, ListUnionMapping, MmapIntervalListMapping, MmapIntervalSetMapping,
+    PayloadPredicate, SetIntersectionMapping, SetUnionMapping
+};
+use rs_intervalset::verify::verify_file;
+
+// A path in the temporary directory that is removed when dropped
+struct TempPath(String);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("rs_intervalset.{}.{}", process::id(), name));
+        TempPath(path.to_str().unwrap().to_owned())
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn write_set(name: &str, index: bool, header: bool) -> TempPath {
+    let path = TempPath::new(name);
+    let mut writer = IntervalSetMappingWriter::new(&path.0, false, index, header, false).unwrap();
+    writer.write(0, &[(0, 10), (20, 30)]).unwrap();
+    writer.write(2, &[(5, 25)]).unwrap();
+    writer.close().unwrap();
+    path
+}
+
+#[test]
+fn test_set_mapping() {
+    for &(index, header) in &[(false, false), (true, false), (false, true), (true, true)] {
+        let path = write_set("set", index, header);
+        let isetmap = MmapIntervalSetMapping::open(&path.0, true, Duplicates::Last).unwrap();
+        assert_eq!(vec![0, 2], isetmap.ids().collect::<Vec<_>>());
+        assert!(!isetmap.has_id(1));
+        assert_eq!(40, isetmap.sum(LengthFilter::default()));
+        assert_eq!(vec![(0, 10), (20, 30)], isetmap.get_intervals(0, Default::default()).unwrap());
+        assert!(isetmap.is_contained(0, 25).unwrap());
+        assert!(!isetmap.is_contained(0, 15).unwrap());
+        assert_eq!(vec![(5, 10), (20, 25)], isetmap.intersect(
+            0, &[(5, 25)], LengthFilter::default()).unwrap());
+        assert_eq!(vec![(10, 20)], isetmap.minus(0, &[(5, 25)]).unwrap());
+        assert_eq!(vec![(0, 30)], isetmap.union(0, &[(5, 25)]).unwrap());
+        assert_eq!(vec![(10, 20), (30, 40)], isetmap.complement(0, 0, 40).unwrap());
+        assert_eq!(vec![(0, 35)], isetmap.dilate(0, 5).unwrap());
+        assert!(isetmap.get_intervals(1, LengthFilter::default()).is_err());
+        assert!(verify_file(&path.0, Some(Kind::Set), None, 10).unwrap().is_ok());
+    }
+}
+
+#[test]
+fn test_list_mapping() {
+    let path = TempPath::new("list");
+    let mut writer = IntervalListMappingWriter::new(&path.0, 1, false, true, true).unwrap();
+    writer.write(0, &[(0, 100, 1), (10, 20, 2), (30, 40, 1)]).unwrap();
+    writer.close().unwrap();
+
+    let ilistmap = MmapIntervalListMapping::open(&path.0, None, Duplicates::Last).unwrap();
+    assert_eq!(1, ilistmap.get_payload_len());
+    let ones = PayloadPredicate::equals(0xff, 1);
+    assert_eq!(vec![(0, 100), (30, 40)],
+               ilistmap.get_intervals(0, &ones, LengthFilter::default()).unwrap());
+    assert!(ilistmap.is_contained(0, 50, &ones).unwrap());
+    assert!(!ilistmap.is_contained(0, 50, &PayloadPredicate::equals(0xff, 2)).unwrap());
+    assert_eq!(vec![(0, 100, 1), (10, 20, 2)], ilistmap.stab(0, 15, &PayloadPredicate::any())
+               .unwrap());
+    assert_eq!(vec![(10, 20), (30, 40)], ilistmap.get_intervals(
+        0, &PayloadPredicate::any(), LengthFilter::new(0, Some(10))).unwrap());
+    assert!(verify_file(&path.0, None, None, 10).unwrap().is_ok());
+}
+
+#[test]
+fn test_combined_mappings() {
+    let a = write_set("combined_a", true, false);
+    let b = TempPath::new("combined_b");
+    let mut writer = IntervalSetMappingWriter::new(&b.0, false, false, false, true).unwrap();
+    writer.write(0, &[(25, 35), (8, 12)]).unwrap();
+    writer.close().unwrap();
+    let isetmaps: Vec<_> = [&a, &b].iter().map(
+        |p| Arc::new(MmapIntervalSetMapping::open(&p.0, false, Duplicates::Last).unwrap())
+    ).collect();
+
+    let union = SetUnionMapping::new(isetmaps.clone());
+    assert_eq!(&[0, 2], union.get_ids());
+    assert_eq!(vec![(0, 12), (20, 35)], union.get_intervals(0).unwrap());
+    let intersection = SetIntersectionMapping::new(isetmaps.clone());
+    assert_eq!(&[0], intersection.get_ids());
+    assert_eq!(vec![(8, 10), (25, 30)], intersection.get_intervals(0).unwrap());
+    assert!(intersection.is_contained(0, 9).unwrap());
+
+    let expr = Expr::minus(Expr::set(isetmaps[0].clone()), Expr::set(isetmaps[1].clone()));
+    assert_eq!(vec![(0, 8), (20, 25)], expr.get_intervals(0).unwrap());
+    assert_eq!(vec![(20, 25)], expr.intersect(0, &[(15, 40)]).unwrap());
+
+    let path = TempPath::new("combined_list");
+    let mut writer = IntervalListMappingWriter::new(&path.0, 0, false, false, false).unwrap();
+    writer.write(0, &[(0, 10, 0), (12, 20, 0)]).unwrap();
+    writer.close().unwrap();
+    let ilistmap = Arc::new(MmapIntervalListMapping::open(
+        &path.0, Some(0), Duplicates::Last).unwrap());
+    let union = ListUnionMapping::new(vec![ilistmap], PayloadPredicate::any(), 5);
+    assert_eq!(vec![(0, 20)], union.get_intervals(0).unwrap());
+    assert_eq!(vec![(0, 10)], union.intersect(0, &[(0, 11)]).unwrap());
+}
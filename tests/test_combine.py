@@ -0,0 +1,206 @@
+import heapq
+import os
+import pytest
+import random
+
+from rs_intervalset import CombinedMapping, ListUnionMapping, \
+    MmapIntervalListMapping, MmapIntervalSetMapping, SetIntersectionMapping, \
+    SetUnionMapping
+from rs_intervalset.wrapper import _deoverlap, MmapUnionIlistsToISetMapping, \
+    MmapISetIntersectionMapping, MmapISetUnionMapping
+from rs_intervalset.writer import IntervalListMappingWriter, \
+    IntervalSetMappingWriter
+
+CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
+ILIST_DATA_PATHS = [
+    os.path.join(CURRENT_DIR, '.combine.test_ilist{}.bin'.format(k))
+    for k in range(3)]
+ISET_DATA_PATHS = [
+    os.path.join(CURRENT_DIR, '.combine.test_iset{}.bin'.format(k))
+    for k in range(3)]
+FUZZ_DATA_PATH = os.path.join(CURRENT_DIR, '.combine.test_fuzz.bin')
+
+N = 100
+M = 100
+MAX_T = 100000
+MAX_SPAN = 2000
+DISTINCT_PAYLOADS = 4
+
+ILIST_TRUTH = []
+ISET_TRUTH = []
+
+
+@pytest.fixture(scope='session', autouse=True)
+def dummy_data():
+    for k, path in enumerate(ILIST_DATA_PATHS):
+        truth = {}
+        with IntervalListMappingWriter(path, 1) as writer:
+            # Each mapping has a different, overlapping range of ids
+            for i in range(k * N // 4, N):
+                intervals = []
+                for j in range(M):
+                    a = random.randint(0, MAX_T - 1)
+                    b = min(MAX_T, a + random.randint(1, MAX_SPAN))
+                    intervals.append(
+                        (a, b, random.randint(0, DISTINCT_PAYLOADS - 1)))
+                intervals.sort()
+                writer.write(i, intervals)
+                truth[i] = intervals
+        ILIST_TRUTH.append(truth)
+    for k, path in enumerate(ISET_DATA_PATHS):
+        truth = {}
+        with IntervalSetMappingWriter(path) as writer:
+            for i in range(k * N // 4, N):
+                intervals = _deoverlap(sorted(
+                    (a, a + random.randint(1, MAX_SPAN)) for a in
+                    random.sample(range(MAX_T - MAX_SPAN), M)), 0)
+                writer.write(i, intervals)
+                truth[i] = intervals
+        ISET_TRUTH.append(truth)
+    yield
+    for path in ILIST_DATA_PATHS + ISET_DATA_PATHS:
+        os.remove(path)
+
+
+def _union_truth(i, mask, value, fuzz):
+    return _deoverlap(heapq.merge(*[
+        [(a, b) for a, b, c in truth[i] if c & mask == value]
+        for truth in ILIST_TRUTH if i in truth
+    ]), fuzz)
+
+
+def _intersect_truth(a, b):
+    return [(max(x[0], y[0]), min(x[1], y[1])) for x in a for y in b
+            if min(x[1], y[1]) > max(x[0], y[0])]
+
+
+def _check_queries(combined, ids, expected_fn, unmerged_fn=None, fuzz=0):
+    # The intersections and containment of a list union are checked against
+    # the intervals before they are merged with fuzz
+    unmerged_fn = unmerged_fn or expected_fn
+    assert len(ids) == combined.len()
+    assert ids == combined.get_ids()
+    total = 0
+    for i in range(N + 1):
+        if i not in ids:
+            assert not combined.has_id(i)
+            with pytest.raises(IndexError):
+                combined.get_intervals(i, False)
+            assert [] == combined.get_intervals(i, True)
+            assert [(0, 10)] == combined.minus(i, [(0, 10)], True)
+            assert not combined.is_contained(i, 0, True)
+            continue
+        assert combined.has_id(i)
+        expected = expected_fn(i)
+        unmerged = unmerged_fn(i)
+        assert expected == combined.get_intervals(i, False)
+        assert len(expected) == combined.get_interval_count(i)
+        total += sum(b - a for a, b in expected)
+
+        query = sorted((t, t + 500) for t in
+                       random.sample(range(0, MAX_T, 1000), 10))
+        intersection = _deoverlap(_intersect_truth(unmerged, query), fuzz)
+        assert intersection == combined.intersect(i, query, False)
+        assert sum(b - a for a, b in intersection) == \
+            combined.intersect_sum(i, query, False)
+
+        for _ in range(10):
+            t = random.randint(0, MAX_T)
+            assert any(a <= t < b for a, b in unmerged) == \
+                combined.is_contained(i, t, False)
+            assert any(min(b, t + 10) > max(a, t) for a, b in unmerged) \
+                == combined.has_intersection(i, t, t + 10, False)
+
+        # Points and windows at the edges of the intervals, where the
+        # intervals read for a query can be cut short
+        for a, b in unmerged[:10]:
+            for t in (max(a - 1, 0), a, b - 1, b):
+                assert any(x <= t < y for x, y in unmerged) == \
+                    combined.is_contained(i, t, False)
+            assert _deoverlap(_intersect_truth(unmerged, [(b - 1, b + 1)]),
+                              fuzz) == \
+                combined.intersect(i, [(b - 1, b + 1)], False)
+
+        covered = combined.intersect_sum(i, [(0, MAX_T)], False)
+        assert MAX_T - covered == \
+            sum(b - a for a, b in combined.minus(i, [(0, MAX_T)], False))
+    assert total == combined.sum()
+
+
+def test_list_union():
+    ilistmaps = [MmapIntervalListMapping(p, 1) for p in ILIST_DATA_PATHS]
+    for mask, value, fuzz in [(0, 0, 0), (1, 1, 0), (3, 2, 100)]:
+        union = ListUnionMapping(ilistmaps, mask, value, fuzz)
+        assert isinstance(union, CombinedMapping)
+        _check_queries(union, list(range(N)),
+                       lambda i: _union_truth(i, mask, value, fuzz),
+                       lambda i: _union_truth(i, mask, value, 0), fuzz)
+
+
+def test_list_union_wrapper():
+    ilistmaps = [MmapIntervalListMapping(p, 1) for p in ILIST_DATA_PATHS]
+    wrapper = MmapUnionIlistsToISetMapping(ilistmaps, 1, 0, 0, fuzz=10)
+    for i in range(N):
+        expected = _union_truth(i, 1, 0, 10)
+        assert expected == wrapper.get_intervals(i, False)
+        assert _deoverlap(_intersect_truth(
+            _union_truth(i, 1, 0, 0), [(0, MAX_T // 2)]), 10) == \
+            wrapper.intersect(i, [(0, MAX_T // 2)], False)
+
+
+def test_list_union_fuzz():
+    # The gaps less than fuzz wide are filled in get_intervals, but not when
+    # checking what a query overlaps, as it was done map by map in Python
+    with IntervalListMappingWriter(FUZZ_DATA_PATH, 1) as writer:
+        writer.write(0, [(0, 10, 0), (12, 20, 0)])
+    try:
+        ilistmap = MmapIntervalListMapping(FUZZ_DATA_PATH, 1)
+        for union in [
+            ListUnionMapping([ilistmap], 0, 0, 5),
+            MmapUnionIlistsToISetMapping([ilistmap], 0, 0, 0, fuzz=5)
+        ]:
+            assert [(0, 20)] == union.get_intervals(0, False)
+            assert [(0, 10)] == union.intersect(0, [(0, 11)], False)
+            assert [(0, 20)] == union.intersect(0, [(0, 20)], False)
+            assert [(5, 15)] == union.intersect(0, [(5, 15)], False)
+            assert 10 == union.intersect_sum(0, [(0, 11)], False)
+            assert not union.is_contained(0, 11, False)
+            assert union.is_contained(0, 12, False)
+            assert not union.has_intersection(0, 10, 12, False)
+    finally:
+        os.remove(FUZZ_DATA_PATH)
+
+
+def _set_union_truth(i):
+    return _deoverlap(heapq.merge(
+        *[truth[i] for truth in ISET_TRUTH if i in truth]), 0)
+
+
+def _set_intersection_truth(i):
+    result = ISET_TRUTH[0][i]
+    for truth in ISET_TRUTH[1:]:
+        result = _intersect_truth(result, truth[i])
+    return result
+
+
+def test_set_union():
+    isetmaps = [MmapIntervalSetMapping(p) for p in ISET_DATA_PATHS]
+    _check_queries(SetUnionMapping(isetmaps), list(range(N)),
+                   _set_union_truth)
+    wrapper = MmapISetUnionMapping(isetmaps)
+    assert _set_union_truth(0) == wrapper.get_intervals(0, False)
+
+
+def test_set_intersection():
+    isetmaps = [MmapIntervalSetMapping(p) for p in ISET_DATA_PATHS]
+    ids = list(range((len(isetmaps) - 1) * N // 4, N))
+    _check_queries(SetIntersectionMapping(isetmaps), ids,
+                   _set_intersection_truth)
+    wrapper = MmapISetIntersectionMapping(isetmaps)
+    assert ids == wrapper.get_ids()
+    assert _set_intersection_truth(N - 1) == \
+        wrapper.get_intervals(N - 1, False)
+
+    assert 0 == SetIntersectionMapping([]).len()
+    single = SetIntersectionMapping(isetmaps[:1])
+    assert ISET_TRUTH[0][0] == single.get_intervals(0, False)
@@ -0,0 +1,211 @@
+import os
+import pytest
+import random
+
+from rs_intervalset import CombinedMapping, Expr, MmapIntervalListMapping, \
+    MmapIntervalSetMapping, PayloadPredicate, SetUnionMapping
+from rs_intervalset.wrapper import _deoverlap
+from rs_intervalset.writer import IntervalListMappingWriter, \
+    IntervalSetMappingWriter
+
+CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
+ISET_DATA_PATHS = [
+    os.path.join(CURRENT_DIR, '.expr.test_iset{}.bin'.format(k))
+    for k in range(2)]
+ILIST_DATA_PATH = os.path.join(CURRENT_DIR, '.expr.test_ilist.bin')
+
+N = 50
+M = 50
+MAX_T = 100000
+MAX_SPAN = 3000
+
+ISET_TRUTH = []
+ILIST_TRUTH = {}
+
+
+@pytest.fixture(scope='session', autouse=True)
+def dummy_data():
+    for k, path in enumerate(ISET_DATA_PATHS):
+        truth = {}
+        with IntervalSetMappingWriter(path) as writer:
+            for i in range(k * N // 2, N):
+                intervals = _deoverlap(sorted(
+                    (a, a + random.randint(1, MAX_SPAN)) for a in
+                    random.sample(range(MAX_T - MAX_SPAN), M)), 0)
+                writer.write(i, intervals)
+                truth[i] = intervals
+        ISET_TRUTH.append(truth)
+    with IntervalListMappingWriter(ILIST_DATA_PATH, 1) as writer:
+        for i in range(N):
+            intervals = []
+            for j in range(M):
+                a = random.randint(0, MAX_T - 1)
+                b = min(MAX_T, a + random.randint(1, MAX_SPAN))
+                intervals.append((a, b, random.randint(0, 3)))
+            intervals.sort()
+            writer.write(i, intervals)
+            ILIST_TRUTH[i] = intervals
+    yield
+    for path in ISET_DATA_PATHS + [ILIST_DATA_PATH]:
+        os.remove(path)
+
+
+# Reference implementations on lists of intervals
+
+def _to_set(l):
+    result = [0] * (MAX_T + 2 * MAX_SPAN)
+    for a, b in l:
+        for t in range(a, b):
+            result[t] = 1
+    return result
+
+
+def _from_set(s):
+    result = []
+    start = None
+    for t, v in enumerate(s + [0]):
+        if v and start is None:
+            start = t
+        elif not v and start is not None:
+            result.append((start, t))
+            start = None
+    return result
+
+
+# Touching intervals are kept apart, so compare results as sets of points
+def _norm(l):
+    return _from_set(_to_set(l))
+
+
+def _and(a, b):
+    return [(max(x[0], y[0]), min(x[1], y[1])) for x in a for y in b
+            if min(x[1], y[1]) > max(x[0], y[0])]
+
+
+def _minus(a, b):
+    sb = _to_set(b)
+    return _from_set([v and not w for v, w in zip(_to_set(a), sb)])
+
+
+def _dilate(a, d):
+    return _deoverlap([(max(0, x - d), y + d) for x, y in a], 1)
+
+
+def _erode(a, d):
+    return [(x + d, y - d) for x, y in _from_set(_to_set(a)) if y - d > x + d]
+
+
+def _list(i, mask, value):
+    return _deoverlap(
+        [(a, b) for a, b, c in ILIST_TRUTH[i] if c & mask == value], 0)
+
+
+def test_leaves():
+    isetmap = MmapIntervalSetMapping(ISET_DATA_PATHS[0])
+    ilistmap = MmapIntervalListMapping(ILIST_DATA_PATH, 1)
+    e = Expr(isetmap)
+    assert isinstance(e, CombinedMapping)
+    assert isetmap.get_ids() == e.get_ids()
+    assert ISET_TRUTH[0][0] == e.get_intervals(0, False)
+    e = Expr(ilistmap, 1, 1)
+    for i in range(N):
+        assert _list(i, 1, 1) == e.get_intervals(i, False)
+    e = Expr(ilistmap, PayloadPredicate.one_of(2 ** 64 - 1, [1, 2]))
+    assert 'Expr(list[payload & 0xffffffffffffffff in {0x1, 0x2}])' == repr(e)
+    for i in range(N):
+        assert _deoverlap([(a, b) for a, b, c in ILIST_TRUTH[i]
+                           if c in (1, 2)], 0) == e.get_intervals(i, False)
+    e = Expr(SetUnionMapping([isetmap]))
+    assert ISET_TRUTH[0][0] == e.get_intervals(0, False)
+    with pytest.raises(TypeError):
+        Expr([])
+
+
+def test_operators():
+    a, b = [Expr(MmapIntervalSetMapping(p)) for p in ISET_DATA_PATHS]
+    l = Expr(MmapIntervalListMapping(ILIST_DATA_PATH, 1), 3, 2)
+
+    both = a & b
+    assert list(range(N // 2, N)) == both.get_ids()
+    either = a | l
+    assert list(range(N)) == either.get_ids()
+    diff = l - b
+    assert list(range(N)) == diff.get_ids()
+
+    for i in range(N):
+        ta = ISET_TRUTH[0][i]
+        tb = ISET_TRUTH[1].get(i, [])
+        tl = _list(i, 3, 2)
+        if i >= N // 2:
+            assert _and(ta, tb) == both.get_intervals(i, False)
+        else:
+            assert not both.has_id(i)
+        assert _deoverlap(sorted(ta + tl), 0) == either.get_intervals(i, False)
+        assert _minus(tl, tb) == _norm(diff.get_intervals(i, False))
+
+    # Mappings are accepted on either side of an operator
+    raw = MmapIntervalSetMapping(ISET_DATA_PATHS[1])
+    assert both.get_intervals(N - 1, False) == \
+        (a & raw).get_intervals(N - 1, False)
+    assert both.get_intervals(N - 1, False) == \
+        (raw & a).get_intervals(N - 1, False)
+
+
+def test_transforms():
+    a = Expr(MmapIntervalSetMapping(ISET_DATA_PATHS[0]))
+    for i in range(0, N, 5):
+        ta = ISET_TRUTH[0][i]
+        assert _dilate(ta, 1000) == a.dilate(1000).get_intervals(i, False)
+        assert _erode(ta, 200) == a.erode(200).get_intervals(i, False)
+        assert _deoverlap(ta, 500) == a.coalesce(500).get_intervals(i, False)
+        assert [(x, y) for x, y in ta if 500 <= y - x <= 1500] == \
+            a.filter_length(500, 1500).get_intervals(i, False)
+        assert [(x, y) for x, y in ta if y - x >= 500] == \
+            a.filter_length(500).get_intervals(i, False)
+
+
+def test_nested():
+    a, b = [MmapIntervalSetMapping(p) for p in ISET_DATA_PATHS]
+    ilistmap = MmapIntervalListMapping(ILIST_DATA_PATH, 1)
+    e = ((Expr(a) & Expr(ilistmap, 1, 0)) - b).dilate(100)
+    assert 'Expr(dilate(((set & list[mask=0x1,value=0x0]) - set), 100))' == \
+        repr(e)
+    total = 0
+    for i in range(N):
+        expected = _dilate(
+            _minus(_and(ISET_TRUTH[0][i], _list(i, 1, 0)),
+                   ISET_TRUTH[1].get(i, [])), 100)
+        assert _norm(expected) == _norm(e.get_intervals(i, False))
+        assert _norm(_and(expected, [(0, MAX_T // 2)])) == \
+            _norm(e.intersect(i, [(0, MAX_T // 2)], False))
+        total += sum(y - x for x, y in expected)
+    assert total == e.sum()
+
+
+def test_windows():
+    # Queries only read the intervals near them, which must give the same
+    # answers as clipping the whole result. The list intervals overlap, so
+    # merging them in a window cuts the ones at its edges short.
+    a, b = [MmapIntervalSetMapping(p) for p in ISET_DATA_PATHS]
+    lst = Expr(MmapIntervalListMapping(ILIST_DATA_PATH, 1), 1, 0)
+    for e in [lst.coalesce(300), lst.dilate(200), lst.erode(100),
+              lst.filter_length(2000), ((lst | a).erode(150) - b).dilate(50),
+              (Expr(a) & lst).coalesce(1000).filter_length(500, 3000)]:
+        for i in range(0, N, 5):
+            full = e.get_intervals(i, False)
+            for _ in range(20):
+                start = random.randint(0, MAX_T)
+                query = [(start, start + random.randint(1, 500)),
+                         (start + 1000, start + random.randint(1001, 3000))]
+                expected = [(max(x, s), min(y, t)) for x, y in full
+                            for s, t in query if min(y, t) > max(x, s)]
+                assert expected == e.intersect(i, query, False), e
+                assert sum(y - x for x, y in expected) == \
+                    e.intersect_sum(i, query, False)
+                assert any(min(y, query[0][1]) > max(x, start)
+                           for x, y in full) == \
+                    e.has_intersection(i, start, query[0][1], False)
+            for x, y in full[:10]:
+                for t in (max(x - 1, 0), x, y - 1, y):
+                    assert any(p <= t < q for p, q in full) == \
+                        e.is_contained(i, t, False), e
@@ -2,13 +2,20 @@ import os
 import pickle
 import pytest
 import random
+from array import array
 
-from rs_intervalset import MmapIntervalListMapping
-from rs_intervalset.writer import IntervalListMappingWriter
+from rs_intervalset import MmapIntervalListMapping, MmapIntervalSetMapping, \
+    PayloadPredicate
+from rs_intervalset.writer import IntervalListMappingWriter, \
+    IntervalSetMappingWriter
 
 CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
 DATA_PATH = os.path.join(CURRENT_DIR, '.ilistmap.test_data.bin')
 TRUTH_PATH = os.path.join(CURRENT_DIR, '.ilistmap.test_truth.bin')
+INDEXED_DATA_PATH = os.path.join(CURRENT_DIR, '.ilistmap.test_indexed.bin')
+HEADERED_DATA_PATH = os.path.join(CURRENT_DIR, '.ilistmap.test_headered.bin')
+DUPLICATE_DATA_PATH = os.path.join(
+    CURRENT_DIR, '.ilistmap.test_duplicate.bin')
 
 PAYLOAD_LEN = 2
 DISTINCT_PAYLOADS = 512
@@ -36,9 +43,23 @@ def dummy_data():
             ground_truth[i] = intervals
         with open(TRUTH_PATH, 'wb') as truth_fh:
             pickle.dump(ground_truth, truth_fh)
+
+    with IntervalListMappingWriter(
+        INDEXED_DATA_PATH, PAYLOAD_LEN, index=True
+    ) as writer:
+        for i in reversed(sorted(ground_truth)):
+            writer.write(i, ground_truth[i])
+
+    with IntervalListMappingWriter(
+        HEADERED_DATA_PATH, PAYLOAD_LEN, index=True, header=True
+    ) as writer:
+        for i in sorted(ground_truth):
+            writer.write(i, ground_truth[i])
     yield
     os.remove(DATA_PATH)
     os.remove(TRUTH_PATH)
+    os.remove(INDEXED_DATA_PATH)
+    os.remove(HEADERED_DATA_PATH)
 
 
 def _load_truth():
@@ -63,6 +84,10 @@ def _deoverlap(l):
     return ret
 
 
+def _points(intervals):
+    return {t for x in intervals for t in range(x[0], x[1])}
+
+
 def test_integrity():
     truth = _load_truth()
     ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
@@ -81,6 +106,85 @@ def test_integrity():
                 == len(ilistmap.get_intervals_with_payload(i, True)))
 
 
+def test_indexed():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(INDEXED_DATA_PATH, PAYLOAD_LEN)
+    assert len(truth) == ilistmap.len()
+    assert sorted(truth.keys()) == ilistmap.get_ids()
+    for i in truth:
+        assert truth[i] == ilistmap.get_intervals_with_payload(i, False)
+
+
+def test_header():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(HEADERED_DATA_PATH)
+    assert PAYLOAD_LEN == ilistmap.get_payload_len()
+    assert sorted(truth.keys()) == ilistmap.get_ids()
+    for i in truth:
+        assert truth[i] == ilistmap.get_intervals_with_payload(i, False)
+
+    with pytest.raises(Exception, match='payload_len'):
+        MmapIntervalListMapping(HEADERED_DATA_PATH, PAYLOAD_LEN + 1)
+    with pytest.raises(Exception, match='payload_len is required'):
+        MmapIntervalListMapping(DATA_PATH)
+    with pytest.raises(Exception, match='not an interval set mapping'):
+        MmapIntervalSetMapping(HEADERED_DATA_PATH)
+
+    # The kind is checked before the length, which need not be a multiple of 4
+    with IntervalListMappingWriter(
+        DUPLICATE_DATA_PATH, 3, index=True, header=True
+    ) as writer:
+        writer.write(0, [(0, 10, 1)])
+    try:
+        assert os.path.getsize(DUPLICATE_DATA_PATH) % 4 != 0
+        with pytest.raises(Exception, match='not an interval set mapping'):
+            MmapIntervalSetMapping(DUPLICATE_DATA_PATH)
+    finally:
+        os.remove(DUPLICATE_DATA_PATH)
+
+
+def test_duplicates():
+    for index in (False, True):
+        with IntervalListMappingWriter(
+            DUPLICATE_DATA_PATH, 1, index=index, header=True
+        ) as writer:
+            writer.write(0, [(0, 10, 1), (20, 30, 2)])
+        with IntervalListMappingWriter(
+            DUPLICATE_DATA_PATH, 1, append=True
+        ) as writer:
+            writer.write(1, [(0, 10, 1)])
+            writer.write(0, [(5, 15, 3), (20, 25, 4)])
+        try:
+            with pytest.raises(Exception, match='id 0 appears 2 times'):
+                MmapIntervalListMapping(
+                    DUPLICATE_DATA_PATH, duplicates='error')
+
+            ilistmap = MmapIntervalListMapping(
+                DUPLICATE_DATA_PATH, duplicates='first')
+            assert [(0, 10, 1), (20, 30, 2)] == \
+                ilistmap.get_intervals_with_payload(0, False)
+
+            for ilistmap in [
+                MmapIntervalListMapping(
+                    DUPLICATE_DATA_PATH, duplicates='last'),
+                MmapIntervalListMapping(DUPLICATE_DATA_PATH)
+            ]:
+                assert [(5, 15, 3), (20, 25, 4)] == \
+                    ilistmap.get_intervals_with_payload(0, False)
+
+            # Merged blocks are sorted by start, keeping the order of ties
+            ilistmap = MmapIntervalListMapping(
+                DUPLICATE_DATA_PATH, duplicates='merge')
+            assert [0, 1] == ilistmap.get_ids()
+            assert [(0, 10, 1), (5, 15, 3), (20, 30, 2), (20, 25, 4)] == \
+                ilistmap.get_intervals_with_payload(0, False)
+            assert [(0, 10, 1)] == ilistmap.get_intervals_with_payload(1, False)
+            assert 4 == ilistmap.get_interval_count(0, 0, 0)
+            assert 1 == ilistmap.get_interval_count(0, 0xFF, 3)
+        finally:
+            os.remove(DUPLICATE_DATA_PATH)
+
+
 def test_contains():
     truth = _load_truth()
     ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
@@ -96,10 +200,262 @@ def test_contains():
     for v in range(MAX_T):
         j = random.randint(0, DISTINCT_PAYLOADS - 1)
         assert truth_contains(v, 0xFFFF, j) == \
-            ilistmap.is_contained(i, v, 0xFFFF, j, False, MAX_SPAN), \
+            ilistmap.is_contained(i, v, 0xFFFF, j, False), \
             'Truth: {}'.format(truth[i])
 
 
+def test_contains_long_interval():
+    # An interval that starts long before the target is found without a
+    # search window
+    with IntervalListMappingWriter(DUPLICATE_DATA_PATH, 1) as writer:
+        writer.write(0, [(0, 100000, 1)] +
+                     [(t, t + 10, 2) for t in range(10, 90000, 100)])
+    try:
+        ilistmap = MmapIntervalListMapping(DUPLICATE_DATA_PATH, 1)
+        assert ilistmap.is_contained(0, 95000, 0xFF, 1, False)
+        with pytest.warns(DeprecationWarning, match='search_window'):
+            assert ilistmap.is_contained(0, 50005, 0, 0, False, 10)
+        assert not ilistmap.is_contained(0, 95000, 0xFF, 2, False)
+        assert ilistmap.is_contained(0, 50015, 0xFF, 2, False)
+        assert not ilistmap.is_contained(0, 50025, 0xFF, 2, False)
+        assert not ilistmap.is_contained(0, 100000, 0, 0, False)
+        assert not ilistmap.is_contained(1, 0, 0, 0, True)
+    finally:
+        os.remove(DUPLICATE_DATA_PATH)
+
+
+def test_stab():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+    i = random.choice(list(truth.keys()))
+    targets = random.sample(range(MAX_T + 1), 1000)
+    for mask, value in [(0, 0), (0xF, 3)]:
+        expected = [[x for x in _filter(truth[i], mask, value)
+                     if x[0] <= t < x[1]] for t in targets]
+        for t, e in zip(targets, expected):
+            assert e == ilistmap.stab(i, t, mask, value, False)
+        assert expected == ilistmap.stab_many(i, targets, mask, value, False)
+        assert expected == ilistmap.stab_many(
+            i, array('I', targets), mask, value, False)
+    assert [] == ilistmap.stab(N, 0, 0, 0, True)
+    assert [[], []] == ilistmap.stab_many(N, [0, 1], 0, 0, True)
+    with pytest.raises(IndexError):
+        ilistmap.stab(N, 0, 0, 0, False)
+
+
+def test_overlapping():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+    i = random.choice(list(truth.keys()))
+    for _ in range(100):
+        start = random.randint(0, MAX_T)
+        end = start + random.randint(0, 2 * MAX_SPAN)
+        for mask, value in [(0, 0), (0xF, 3)]:
+            assert [x for x in _filter(truth[i], mask, value)
+                    if x[0] < end and x[1] > start] == \
+                ilistmap.overlapping(i, start, end, mask, value, False)
+    assert [] == ilistmap.overlapping(i, 10, 10, 0, 0, False)
+    assert [] == ilistmap.overlapping(N, 0, MAX_T, 0, 0, True)
+
+
+def test_small_windows():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+
+    # The first window reads the whole id, and the later ones only the
+    # intervals that can overlap them
+    i = random.choice(list(truth.keys()))
+    for _ in range(100):
+        start = random.randint(0, MAX_T)
+        window = [(start, start + random.randint(1, 100)),
+                  (start + 200, start + 300)]
+        for mask, value in [(0, 0), (0xF, 3)]:
+            matching = [(a, b) for a, b, _ in _filter(truth[i], mask, value)]
+            assert _points(window) & _points(matching) == _points(
+                ilistmap.intersect(i, window, mask, value, False))
+            assert sum(
+                max(0, min(b, y) - max(a, x)) for a, b in matching
+                for x, y in window
+            ) == ilistmap.intersect_sum(i, window, mask, value, False)
+
+
+def test_complement():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+
+    i = random.choice(list(truth.keys()))
+    lengths = [MAX_T] * N
+    for mask, value in [(0, 0), (0xFF, 7)]:
+        expected = set(range(MAX_T)) - _points(_filter(truth[i], mask, value))
+        res = ilistmap.complement(i, 0, lengths, mask, value, False)
+        assert expected == _points(res)
+        assert all(x[1] < y[0] for x, y in zip(res, res[1:]))
+        assert len(expected) == ilistmap.complement_sum(
+            i, 0, MAX_T, mask, value, False)
+    assert [(5, 10)] == ilistmap.complement(N, 5, 10, 0, 0, True)
+
+
+def test_morphology():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+
+    i = random.choice(list(truth.keys()))
+    for mask, value in [(0, 0), (0xF, 3)]:
+        # Compare to the same operations on the merged intervals
+        ints = _deoverlap(
+            (a, b) for a, b, _ in _filter(truth[i], mask, value))
+        with IntervalSetMappingWriter(DATA_PATH + '.set') as writer:
+            writer.write(i, ints)
+        try:
+            isetmap = MmapIntervalSetMapping(DATA_PATH + '.set')
+            for op in ['dilate', 'erode', 'coalesce', 'opening',
+                       'closing']:
+                assert getattr(isetmap, op)(i, 100, False) == getattr(
+                    ilistmap, op)(i, 100, mask, value, False), op
+        finally:
+            os.remove(DATA_PATH + '.set')
+    assert [] == ilistmap.closing(N, 100, 0, 0, True)
+
+
+def test_length_filter():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+    lo, hi = MAX_SPAN // 4, MAX_SPAN // 2
+
+    def keep(intervals, mask, value):
+        return [(a, b) for a, b, _ in _filter(intervals, mask, value)
+                if lo <= b - a <= hi]
+
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        for mask, value in [(0, 0), (0xF, 3)]:
+            expected = keep(truth[i], mask, value)
+            assert expected == ilistmap.get_intervals(
+                i, mask, value, False, min_len=lo, max_len=hi)
+            assert _deoverlap(expected) == ilistmap.intersect(
+                i, [(0, MAX_T)], mask, value, False, lo, hi)
+            assert sum(b - a for a, b in expected) == ilistmap.intersect_sum(
+                i, [(0, MAX_T)], mask, value, False, min_len=lo, max_len=hi)
+    assert sum(b - a for ints in truth.values()
+               for a, b in keep(ints, 0, 0)) == ilistmap.sum(0, 0, lo, hi)
+    assert [] == ilistmap.get_intervals(0, 0, 0, False, min_len=MAX_T + 1)
+
+
+def test_depth():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+
+    def depths(intervals):
+        counts = [0] * (MAX_T + 1)
+        for a, b, _ in intervals:
+            for t in range(a, b):
+                counts[t] += 1
+        return counts
+
+    i = random.choice(list(truth.keys()))
+    for mask, value in [(0, 0), (0xF, 3)]:
+        counts = depths(_filter(truth[i], mask, value))
+        profile = ilistmap.depth_profile(i, mask, value, False)
+        assert counts == depths(
+            [(a, b, None) for a, b, c in profile for _ in range(c)])
+        assert all(c > 0 for _, _, c in profile)
+        assert all(x[1] < y[0] or x[2] != y[2]
+                   for x, y in zip(profile, profile[1:]))
+        for k in [1, 2, 5]:
+            res = ilistmap.min_depth(i, k, mask, value, False)
+            assert {t for t, c in enumerate(counts) if c >= k} == _points(res)
+            assert all(x[1] < y[0] for x, y in zip(res, res[1:]))
+
+    # An interval that starts where another ends does not split a segment
+    with IntervalListMappingWriter(DUPLICATE_DATA_PATH, 1) as writer:
+        writer.write(0, [(0, 10, 0), (5, 20, 0), (10, 20, 0), (30, 40, 1)])
+    try:
+        ilistmap = MmapIntervalListMapping(DUPLICATE_DATA_PATH, 1)
+        assert [(0, 5, 1), (5, 20, 2), (30, 40, 1)] == \
+            ilistmap.depth_profile(0, 0, 0, False)
+        assert [(5, 20)] == ilistmap.min_depth(0, 2, 0, 0, False)
+        assert [(0, 20), (30, 40)] == ilistmap.min_depth(0, 1, 0, 0, False)
+        assert [] == ilistmap.depth_profile(1, 0, 0, True)
+    finally:
+        os.remove(DUPLICATE_DATA_PATH)
+
+
+def test_sum_by_payload():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+
+    def expected(ids, mask, deoverlap):
+        res = {}
+        for i in ids:
+            groups = {}
+            for a, b, c in truth[i]:
+                groups.setdefault(c & mask, []).append((a, b))
+            for key, intervals in groups.items():
+                if deoverlap:
+                    intervals = _deoverlap(intervals)
+                total, count = res.get(key, (0, 0))
+                res[key] = (total + sum(b - a for a, b in intervals),
+                            count + len(intervals))
+        return res
+
+    i = random.choice(list(truth.keys()))
+    for mask in [0, 0xF, 0xFFFF]:
+        for deoverlap in [False, True]:
+            assert expected([i], mask, deoverlap) == \
+                ilistmap.sum_by_payload(i, mask, deoverlap)
+    assert expected(truth.keys(), 0x3, True) == \
+        ilistmap.sum_by_payload(None, 0x3, deoverlap=True)
+    assert expected(truth.keys(), 0xFF, False) == \
+        ilistmap.sum_by_payload(None, 0xFF)
+    assert {} == ilistmap.sum_by_payload(N, 0xFF, use_default=True)
+    with pytest.raises(IndexError):
+        ilistmap.sum_by_payload(N, 0xFF)
+
+
+def test_payload_predicate():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+    high = PayloadPredicate.range(2 ** 64 - 1, 0xC0, 0x1FF)
+    odd_low = PayloadPredicate.one_of(0xF, [1, 3, 5, 7])
+    predicates = [
+        (PayloadPredicate.equals(0xF, 3), lambda c: c & 0xF == 3),
+        (high, lambda c: 0xC0 <= c <= 0x1FF),
+        (odd_low, lambda c: c & 0xF in (1, 3, 5, 7)),
+        (PayloadPredicate.range(0x100, 0x100, 0x100),
+         lambda c: c & 0x100 == 0x100),
+        (high & odd_low, lambda c: c >= 0xC0 and c & 0xF in (1, 3, 5, 7)),
+        (high | odd_low, lambda c: c >= 0xC0 or c & 0xF in (1, 3, 5, 7)),
+        (~high, lambda c: c < 0xC0),
+    ]
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        for pred, f in predicates:
+            expected = [(a, b) for a, b, c in truth[i] if f(c)]
+            assert all(pred.matches(c) == f(c) for _, _, c in truth[i])
+            assert expected == ilistmap.get_intervals(i, pred, None, False)
+            assert len(expected) == ilistmap.get_interval_count(i, pred)
+            assert _deoverlap(expected) == ilistmap.intersect(
+                i, [(0, MAX_T)], pred, None, False)
+            assert sum(b - a for a, b in expected) == ilistmap.intersect_sum(
+                i, [(0, MAX_T)], pred, None, False)
+            target = random.randint(0, MAX_T - 1)
+            assert any(a <= target < b for a, b in expected) == \
+                ilistmap.is_contained(i, target, pred, None, False)
+    assert sum(b - a for ints in truth.values() for a, b, c in ints
+               if c & 0xF == 3) == \
+        ilistmap.sum(PayloadPredicate.equals(0xF, 3))
+
+    assert 'PayloadPredicate((payload & 0xf in {0x1, 0x3, 0x5, 0x7} and ' \
+        'not (payload & 0xffffffffffffffff in 0xc0..=0x1ff)))' == \
+        repr(odd_low & ~high)
+    with pytest.raises(TypeError):
+        ilistmap.get_intervals(0, high, 1, False)
+    with pytest.raises(TypeError):
+        ilistmap.get_intervals(0, 0xF, None, False)
+    with pytest.raises(TypeError):
+        high & 1
+
+
 def _is_close(a: float, b: float, t: float = 1e-6) -> bool:
     return abs(a - b) <= t
 
@@ -138,3 +494,43 @@ def test_intersect():
             assert (
                 true_iset
                 == ilistmap.intersect(i, [(0, MAX_T)], 0xFFFF, j, False))
+
+
+def test_numpy():
+    np = pytest.importorskip('numpy')
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        arr = ilistmap.get_intervals_array(i, 0, 0, False)
+        assert arr.dtype == np.uint32 and arr.shape == (len(truth[i]), 2)
+        assert [(a, b) for a, b, _ in truth[i]] == \
+            [tuple(x) for x in arr.tolist()]
+
+        arr, payload = ilistmap.get_intervals_with_payload_array(i, False)
+        assert payload.dtype == np.uint16
+        assert [c for _, _, c in truth[i]] == payload.tolist()
+
+        j = random.randrange(DISTINCT_PAYLOADS)
+        assert ilistmap.get_intervals(i, 0xFFFF, j, False) == [
+            tuple(x) for x in
+            ilistmap.get_intervals_array(i, 0xFFFF, j, False).tolist()]
+        assert ilistmap.intersect(i, [(0, MAX_T)], 0xFFFF, j, False) == [
+            tuple(x) for x in ilistmap.intersect_array(
+                i, [(0, MAX_T)], 0xFFFF, j, False).tolist()]
+
+
+def test_array_input():
+    truth = _load_truth()
+    ilistmap = MmapIntervalListMapping(DATA_PATH, PAYLOAD_LEN)
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        query = _deoverlap(
+            (a, b) for a, b, _ in truth[random.choice(list(truth.keys()))])
+        flat = array('I', [x for row in query for x in row])
+        buf = memoryview(flat).cast('B').cast('I', shape=[len(query), 2])
+        j = random.randrange(DISTINCT_PAYLOADS)
+        assert ilistmap.intersect(i, query, 0xFFFF, j, False) == \
+            ilistmap.intersect(i, buf, 0xFFFF, j, False)
+        assert ilistmap.intersect_sum(i, query, 0, 0, False) == \
+            ilistmap.intersect_sum(i, buf, 0, 0, False)
@@ -2,14 +2,21 @@ import os
 import pickle
 import pytest
 import random
+from array import array
+from concurrent.futures import ThreadPoolExecutor
 
 from rs_intervalset import MmapIntervalSetMapping
-from rs_intervalset.writer import IntervalSetMappingWriter
+from rs_intervalset.writer import IntervalSetMappingWriter, \
+    IntervalListMappingWriter
 
 
 CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
 DATA_PATH = os.path.join(CURRENT_DIR, '.isetmap.test_data.bin')
 TRUTH_PATH = os.path.join(CURRENT_DIR, '.isetmap.test_truth.bin')
+INDEXED_DATA_PATH = os.path.join(CURRENT_DIR, '.isetmap.test_indexed.bin')
+INVALID_DATA_PATH = os.path.join(CURRENT_DIR, '.isetmap.test_invalid.bin')
+DUPLICATE_DATA_PATH = os.path.join(
+    CURRENT_DIR, '.isetmap.test_duplicate.bin')
 
 
 N = 1000
@@ -39,9 +46,19 @@ def dummy_data():
             ground_truth[i] = intervals
         with open(TRUTH_PATH, 'wb') as truth_fh:
             pickle.dump(ground_truth, truth_fh)
+
+    # Write the ids in two passes and in reverse order to exercise the index
+    ids = list(reversed(sorted(ground_truth)))
+    with IntervalSetMappingWriter(INDEXED_DATA_PATH, index=True) as writer:
+        for i in ids[:N // 2]:
+            writer.write(i, ground_truth[i])
+    with IntervalSetMappingWriter(INDEXED_DATA_PATH, append=True) as writer:
+        for i in ids[N // 2:]:
+            writer.write(i, ground_truth[i])
     yield
     os.remove(DATA_PATH)
     os.remove(TRUTH_PATH)
+    os.remove(INDEXED_DATA_PATH)
 
 
 def _load_truth():
@@ -62,6 +79,71 @@ def test_integrity():
             assert interval == isetmap.get_interval(i, j)
 
 
+def test_indexed():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(INDEXED_DATA_PATH)
+    assert len(truth) == isetmap.len()
+    assert sorted(truth.keys()) == isetmap.get_ids()
+    assert not isetmap.has_id(N)
+    for i in truth:
+        assert isetmap.has_id(i)
+        assert truth[i] == isetmap.get_intervals(i, False)
+    assert sum(b - a for v in truth.values() for a, b in v) == isetmap.sum()
+
+
+def test_strict():
+    isetmap = MmapIntervalSetMapping(DATA_PATH, strict=True)
+    assert N == isetmap.len()
+
+    # A list mapping without payload has the same layout, but is not validated
+    with IntervalListMappingWriter(INVALID_DATA_PATH, 0) as writer:
+        writer.write(0, [(0, 10, 0)])
+        writer.write(1, [(0, 10, 0), (5, 15, 0)])
+    try:
+        assert MmapIntervalSetMapping(INVALID_DATA_PATH).len() == 2
+        with pytest.raises(Exception, match='id 1 interval 1: .* overlaps'):
+            MmapIntervalSetMapping(INVALID_DATA_PATH, strict=True)
+    finally:
+        os.remove(INVALID_DATA_PATH)
+
+
+def test_duplicates():
+    for index in (False, True):
+        with IntervalSetMappingWriter(DUPLICATE_DATA_PATH, index=index) as writer:
+            writer.write(0, [(0, 10), (20, 30)])
+            writer.write(1, [(0, 10)])
+        with IntervalSetMappingWriter(DUPLICATE_DATA_PATH, append=True) as writer:
+            writer.write(0, [(5, 15), (40, 50)])
+            writer.write(2, [(0, 10)])
+        try:
+            with pytest.raises(Exception, match='id 0 appears 2 times'):
+                MmapIntervalSetMapping(DUPLICATE_DATA_PATH, duplicates='error')
+            with pytest.raises(ValueError, match='duplicates must be'):
+                MmapIntervalSetMapping(DUPLICATE_DATA_PATH, duplicates='none')
+
+            isetmap = MmapIntervalSetMapping(
+                DUPLICATE_DATA_PATH, duplicates='first')
+            assert [0, 1, 2] == isetmap.get_ids()
+            assert [(0, 10), (20, 30)] == isetmap.get_intervals(0, False)
+
+            for isetmap in [
+                MmapIntervalSetMapping(DUPLICATE_DATA_PATH, duplicates='last'),
+                MmapIntervalSetMapping(DUPLICATE_DATA_PATH)
+            ]:
+                assert [(5, 15), (40, 50)] == isetmap.get_intervals(0, False)
+
+            isetmap = MmapIntervalSetMapping(
+                DUPLICATE_DATA_PATH, strict=True, duplicates='merge')
+            assert 3 == isetmap.len()
+            assert [(0, 15), (20, 30), (40, 50)] == \
+                isetmap.get_intervals(0, False)
+            assert [(0, 10)] == isetmap.get_intervals(2, False)
+            assert isetmap.is_contained(0, 12, False)
+            assert 55 == isetmap.sum()
+        finally:
+            os.remove(DUPLICATE_DATA_PATH)
+
+
 def _is_close(a: float, b: float, t: float = 1e-6) -> bool:
     return abs(a - b) <= t
 
@@ -150,3 +232,229 @@ def test_minus():
         i = random.choice(list(truth.keys()))
         assert naive_minus(truth[i], 0, MAX_T) == \
             isetmap.minus(i, [(0, MAX_T)], False)
+
+
+def _points(intervals):
+    return {t for x in intervals for t in range(x[0], x[1])}
+
+
+def _random_window(max_len):
+    start = random.randint(0, MAX_T)
+    points = sorted(random.sample(
+        range(start, start + max_len), random.randint(0, 3) * 2))
+    return list(zip(points[::2], points[1::2]))
+
+
+def test_small_windows():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+
+    for _ in range(100):
+        i = random.choice(list(truth.keys()))
+        window = _random_window(3 * MAX_T_SPAN)
+        expected = _points(window) & _points(truth[i])
+        res = isetmap.intersect(i, window, False)
+        assert expected == _points(res)
+        assert sorted(res) == res
+        assert len(expected) == isetmap.intersect_sum(i, window, False)
+        res = isetmap.minus(i, window, False)
+        assert _points(window) - _points(truth[i]) == _points(res)
+        assert sorted(res) == res
+
+
+def test_union_and_differences():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        other = [(a + MAX_T_SPAN // 2, b + MAX_T_SPAN // 2)
+                 for a, b in random.sample(truth[i], len(truth[i]) // 2)]
+        other.sort()
+        stored, provided = _points(truth[i]), _points(other)
+        res = isetmap.union(i, other, False)
+        assert stored | provided == _points(res)
+        assert all(x[1] <= y[0] for x, y in zip(res, res[1:]))
+        assert len(stored | provided) == isetmap.union_sum(i, other, False)
+        res = isetmap.symmetric_difference(i, other, False)
+        assert stored ^ provided == _points(res)
+        assert sorted(res) == res
+        res = isetmap.difference_from(i, other, False)
+        assert stored - provided == _points(res)
+        assert sorted(res) == res
+
+    assert [(0, 10)] == isetmap.union(N, [(0, 5), (3, 10)], True)
+    assert 10 == isetmap.union_sum(N, [(0, 5), (3, 10)], True)
+    assert [(0, 10)] == isetmap.symmetric_difference(N, [(0, 10)], True)
+    assert [] == isetmap.difference_from(N, [(0, 10)], True)
+
+
+def test_complement():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+
+    lengths = {i: MAX_T - random.randint(0, MAX_T_SPAN) for i in truth}
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        lo = random.randint(0, MAX_T // 2)
+        hi = lo + random.randint(0, 5 * MAX_T_SPAN)
+        expected = set(range(lo, hi)) - _points(truth[i])
+        res = isetmap.complement(i, lo, hi, False)
+        assert expected == _points(res)
+        assert all(x[1] < y[0] for x, y in zip(res, res[1:]))
+        assert len(expected) == isetmap.complement_sum(i, lo, hi, False)
+
+        # The bound can be looked up by id
+        expected = set(range(0, lengths[i])) - _points(truth[i])
+        assert expected == _points(isetmap.complement(i, 0, lengths, False))
+        assert len(expected) == isetmap.complement_sum(i, 0, lengths, False)
+
+    assert [] == isetmap.complement(0, 10, 10, False)
+    assert [(0, 10)] == isetmap.complement(N, 0, 10, True)
+    assert 10 == isetmap.complement_sum(N, 0, 10, True)
+    with pytest.raises(KeyError):
+        isetmap.complement(N, 0, lengths, True)
+
+
+def test_morphology():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+
+    def dilate(intervals, d):
+        return _points((max(0, a - d), b + d) for a, b in intervals)
+
+    def erode(intervals, d):
+        p = _points(intervals)
+        return {t for t in p
+                if all(u in p for u in range(max(0, t - d), t + d + 1))
+                and t >= d}
+
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        ints = truth[i]
+        d = random.randint(1, MAX_SKIP)
+        assert dilate(ints, d) == _points(isetmap.dilate(i, d, False))
+        assert erode(ints, d) == _points(isetmap.erode(i, d, False))
+        res = isetmap.coalesce(i, d, False)
+        assert _points(ints) | {t for x, y in zip(ints, ints[1:])
+                               if y[0] - x[1] < d
+                               for t in range(x[1], y[0])} == _points(res)
+        assert all(y[0] - x[1] >= d for x, y in zip(res, res[1:]))
+        assert {t for t in _points(ints)
+                if any(y - x > 2 * d and x <= t < y for x, y in ints)} == \
+            _points(isetmap.opening(i, d, False))
+        assert _points(isetmap.coalesce(i, 2 * d + 1, False)) == \
+            _points(isetmap.closing(i, d, False))
+
+    # Closing does not shrink the intervals at the bounds
+    end = 2 ** 32 - 1
+    with IntervalSetMappingWriter(DUPLICATE_DATA_PATH) as writer:
+        writer.write(0, [(0, 10), (15, 20), (end - 9, end)])
+        writer.write(1, [(0, 10), (14, 20)])
+        writer.write(3, [(0, 10), (10, 20)])
+    try:
+        isetmap = MmapIntervalSetMapping(DUPLICATE_DATA_PATH)
+        assert [(0, 20), (end - 9, end)] == isetmap.closing(0, 3, False)
+        assert [(0, 10), (end - 9, end)] == isetmap.opening(0, 3, False)
+        assert [(0, 25), (end - 14, end)] == isetmap.dilate(0, 5, False)
+        assert [] == isetmap.erode(0, 5, False)
+        assert [] == isetmap.dilate(2, 5, True)
+
+        # Intervals that touch after dilation are merged, as by closing, but
+        # coalesce only merges them if the gap is non-zero
+        assert [(0, 22)] == isetmap.dilate(1, 2, False)
+        assert [(0, 20)] == isetmap.dilate(3, 0, False)
+        assert [(0, 10), (10, 20)] == isetmap.coalesce(3, 0, False)
+        assert [(0, 20)] == isetmap.coalesce(3, 1, False)
+    finally:
+        os.remove(DUPLICATE_DATA_PATH)
+
+
+def test_length_filter():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+    lo, hi = MAX_T_SPAN // 4, MAX_T_SPAN // 2
+    window = [(MAX_T // 4, MAX_T // 2)]
+
+    def keep(intervals, max_len):
+        return [(a, b) for a, b in intervals
+                if b - a >= lo and (max_len is None or b - a <= max_len)]
+
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        for max_len in [None, hi]:
+            expected = keep(truth[i], max_len)
+            assert expected == isetmap.get_intervals(
+                i, False, min_len=lo, max_len=max_len)
+            assert expected == isetmap.intersect(
+                i, [(0, MAX_T)], False, lo, max_len)
+            clipped = [(max(a, window[0][0]), min(b, window[0][1]))
+                       for a, b in expected
+                       if min(b, window[0][1]) > max(a, window[0][0])]
+            assert clipped == isetmap.intersect(
+                i, window, False, min_len=lo, max_len=max_len)
+            assert sum(b - a for a, b in clipped) == isetmap.intersect_sum(
+                i, window, False, min_len=lo, max_len=max_len)
+    assert sum(b - a for ints in truth.values() for a, b in keep(ints, hi)) \
+        == isetmap.sum(lo, hi)
+    assert sum(b - a for ints in truth.values() for a, b in ints) == \
+        isetmap.sum()
+
+
+def test_numpy():
+    np = pytest.importorskip('numpy')
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        arr = isetmap.get_intervals_array(i, False)
+        assert arr.dtype == np.uint32 and arr.shape == (len(truth[i]), 2)
+        assert truth[i] == [tuple(x) for x in arr.tolist()]
+        assert isetmap.intersect(i, [(0, MAX_T // 2)], False) == [
+            tuple(x) for x in
+            isetmap.intersect_array(i, [(0, MAX_T // 2)], False).tolist()]
+        assert isetmap.minus(i, [(0, MAX_T)], False) == [
+            tuple(x) for x in
+            isetmap.minus_array(i, [(0, MAX_T)], False).tolist()]
+    assert (0, 2) == isetmap.get_intervals_array(N, True).shape
+    with pytest.raises(IndexError):
+        isetmap.get_intervals_array(N, False)
+
+
+def _as_buffer(intervals):
+    """Get an (n, 2) buffer without depending on numpy"""
+    flat = array('I', [x for row in intervals for x in row])
+    return memoryview(flat).cast('B').cast('I', shape=[len(intervals), 2])
+
+
+def test_array_input():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+    for _ in range(N_REPEAT):
+        i = random.choice(list(truth.keys()))
+        query = truth[random.choice(list(truth.keys()))]
+        buf = _as_buffer(query)
+        assert isetmap.intersect(i, query, False) == \
+            isetmap.intersect(i, buf, False)
+        assert isetmap.intersect_sum(i, query, False) == \
+            isetmap.intersect_sum(i, buf, False)
+        assert isetmap.minus(i, query, False) == isetmap.minus(i, buf, False)
+    assert [(1, 2)] == isetmap.minus(N, _as_buffer([(1, 2)]), True)
+    with pytest.raises(ValueError, match='shape'):
+        isetmap.intersect(0, memoryview(array('I', [1, 2])), False)
+
+
+def test_threads():
+    truth = _load_truth()
+    isetmap = MmapIntervalSetMapping(DATA_PATH)
+    total = sum(b - a for v in truth.values() for a, b in v)
+
+    def query(i):
+        assert total == isetmap.sum()
+        assert truth[i] == isetmap.get_intervals(i, False)
+        return isetmap.intersect_sum(i, [(0, MAX_T)], False)
+
+    with ThreadPoolExecutor(max_workers=4) as executor:
+        ids = list(truth.keys())
+        assert [sum(b - a for a, b in truth[i]) for i in ids] == \
+            list(executor.map(query, ids))
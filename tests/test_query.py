@@ -0,0 +1,130 @@
+import os
+import pytest
+import re
+
+from rs_intervalset import Expr, MmapIntervalListMapping, \
+    MmapIntervalSetMapping, Query
+from rs_intervalset.writer import IntervalListMappingWriter, \
+    IntervalSetMappingWriter
+
+CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
+FACES_PATH = os.path.join(CURRENT_DIR, '.query.test_faces.bin')
+CAPTIONS_PATH = os.path.join(CURRENT_DIR, '.query.test_captions.bin')
+COMMERCIALS_PATH = os.path.join(CURRENT_DIR, '.query.test_commercials.bin')
+
+
+@pytest.fixture(scope='session', autouse=True)
+def dummy_data():
+    with IntervalListMappingWriter(FACES_PATH, 1) as writer:
+        writer.write(0, [(0, 100, 3), (50, 150, 1), (200, 300, 3),
+                         (400, 500, 0x13)])
+        writer.write(1, [(0, 10, 3)])
+    with IntervalSetMappingWriter(CAPTIONS_PATH) as writer:
+        writer.write(0, [(0, 250), (450, 1000)])
+    with IntervalSetMappingWriter(COMMERCIALS_PATH) as writer:
+        writer.write(0, [(20, 30), (260, 270)])
+    yield
+    for path in (FACES_PATH, CAPTIONS_PATH, COMMERCIALS_PATH):
+        os.remove(path)
+
+
+def _bindings():
+    return {
+        'faces': MmapIntervalListMapping(FACES_PATH, 1),
+        'captions': MmapIntervalSetMapping(CAPTIONS_PATH),
+        'commercials': MmapIntervalSetMapping(COMMERCIALS_PATH),
+    }
+
+
+def test_precedence():
+    q = Query('faces[mask=0xff,value=3] & captions - commercials '
+              '| dilate(10)')
+    assert ['captions', 'commercials', 'faces'] == q.names()
+    assert q.explain() == '\n'.join([
+        'Dilate 10',
+        '  Minus',
+        '    Intersect',
+        '      faces [payload & 0xff == 0x3]',
+        '      captions',
+        '    commercials',
+        ''])
+    e = q.compile(_bindings())
+    assert isinstance(e, Expr)
+    assert [0] == e.get_ids()
+    assert [(0, 110), (190, 260)] == e.get_intervals(0, False)
+
+    # | is a union unless it is followed by a transform
+    e = Query('captions - commercials | faces[value=0x13]').compile(
+        _bindings())
+    assert [0, 1] == e.get_ids()
+    assert [(0, 20), (30, 250), (400, 1000)] == e.get_intervals(0, False)
+
+
+def test_transforms():
+    bindings = _bindings()
+    for text in ['dilate(faces, 10)', 'faces | dilate(10)',
+                 '(faces | dilate(10))']:
+        assert [(0, 160), (190, 310), (390, 510)] == \
+            Query(text).compile(bindings).get_intervals(0, False)
+    assert [(200, 300), (400, 500)] == Query(
+        'faces | filter_length(100, 100)').compile(
+        bindings).get_intervals(0, False)
+    assert [(0, 300), (400, 500)] == Query('coalesce(faces, 51)').compile(
+        bindings).get_intervals(0, False)
+    assert [(10, 140), (210, 290), (410, 490)] == Query(
+        'faces | erode(10)').compile(bindings).get_intervals(0, False)
+
+    # Expressions can be bound to names
+    bindings['hosts'] = Expr(bindings['faces'], 0xff, 3)
+    assert [(0, 100), (200, 300)] == Query('hosts').compile(
+        bindings).get_intervals(0, False)
+
+
+def test_payload_filters():
+    bindings = _bindings()
+    for text, expected in [
+        ('faces[mask=0xf,in=(1,3)]', [(0, 150), (200, 300), (400, 500)]),
+        ('faces[in=(0x13)]', [(400, 500)]),
+        ('faces[min=2,max=0x10]', [(0, 100), (200, 300)]),
+        ('faces[mask=0xf0,min=0x10]', [(400, 500)]),
+        ('faces[max=1]', [(50, 150)]),
+    ]:
+        assert expected == Query(text).compile(bindings).get_intervals(
+            0, False)
+    assert 'faces [payload & 0xf in {0x1, 0x3}]\n' == \
+        Query('faces[mask=0xf,in=(3,1,3)]').explain()
+    assert 'faces [payload & 0xff in 0x2..=0x10]\n' == \
+        Query('faces[min=2,mask=0xff,max=0x10]').explain()
+
+
+def test_errors():
+    for text, message in [
+        ('faces &', 'expected a name or \'(\', found end of query at '
+                    'position 7'),
+        ('faces captions', 'expected an operator, found \'captions\''),
+        ('(faces', 'expected \')\', found end of query'),
+        ('faces[mask=1,mask=2]', '\'mask\' is given twice at position 13'),
+        ('faces[size=1]', 'unknown filter \'size\''),
+        ('faces[value=1,in=(1,2)]', '\'in\' cannot be combined with '
+                                    '\'value\' at position 14'),
+        ('faces[min=1,value=2]', '\'value\' cannot be combined with '
+                                 '\'min\''),
+        ('faces[in=()]', 'expected a number, found \')\''),
+        ('dilate(faces)', 'expected \',\', found \')\''),
+        ('faces | dilate(1, 2)', 'dilate takes one number at position 8'),
+        ('faces | dilate(0x100000000)', 'out of range'),
+        ('faces $ captions', 'unexpected character \'$\' at position 6'),
+        ('faces | dilate(12a)', 'invalid number \'12a\''),
+    ]:
+        with pytest.raises(ValueError, match=re.escape(message)):
+            Query(text)
+
+    with pytest.raises(ValueError) as e:
+        Query('faces & capions').compile(_bindings())
+    assert 'unknown name \'capions\' at position 8\n' \
+        '  faces & capions\n' \
+        '          ^' == str(e.value)
+    with pytest.raises(ValueError, match='not a list mapping'):
+        Query('captions[value=1]').compile(_bindings())
+    with pytest.raises(TypeError):
+        Query('faces').compile({'faces': 1})
@@ -0,0 +1,93 @@
+import os
+import pytest
+import struct
+
+from rs_intervalset import verify, IntervalListMappingWriter
+
+
+CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
+DATA_PATH = os.path.join(CURRENT_DIR, '.verify.test_data.bin')
+
+
+@pytest.fixture(scope='session', autouse=True)
+def cleanup():
+    yield
+    if os.path.exists(DATA_PATH):
+        os.remove(DATA_PATH)
+
+
+def _write_raw(records, payload_len=0, trailing=b''):
+    with open(DATA_PATH, 'wb') as f:
+        for id_, intervals in records:
+            f.write(struct.pack('<II', id_, len(intervals)))
+            for a, b in intervals:
+                f.write(struct.pack('<II', a, b))
+                f.write(b'\0' * payload_len)
+        f.write(trailing)
+
+
+def _problems(report):
+    return [(p['offset'], p['id'], p['index'], p['kind'])
+            for p in report['problems']]
+
+
+def test_valid():
+    with IntervalListMappingWriter(DATA_PATH, 2, index=True,
+                                   header=True) as writer:
+        for i in range(10):
+            writer.write(i, [(j, j + 5, j) for j in range(100)])
+    report = verify(DATA_PATH)
+    assert report['ok']
+    assert report['kind'] == 'list'
+    assert report['payload_len'] == 2
+    assert report['has_header'] and report['has_index']
+    assert report['id_count'] == 10
+    assert report['interval_count'] == 1000
+    assert report['problems'] == []
+
+
+def test_set_problems():
+    _write_raw([(5, [(1, 2), (3, 3)]),
+                (3, [(4, 9), (2, 8), (10, 9)]),
+                (5, [(1, 4), (2, 3)])])
+    report = verify(DATA_PATH, 'set')
+    assert not report['ok']
+    assert _problems(report) == [
+        (16, 5, 1, 'empty_interval'),
+        (24, 3, None, 'unsorted_id'),
+        (40, 3, 1, 'unsorted_interval'),
+        (48, 3, 2, 'inverted_interval'),
+        (56, 5, None, 'duplicate_id'),
+        (72, 5, 1, 'overlapping_interval'),
+    ]
+
+    # Overlaps are allowed in list mappings
+    report = verify(DATA_PATH, 'list', 0)
+    assert 'overlapping_interval' not in [p[3] for p in _problems(report)]
+
+
+def test_truncated():
+    _write_raw([(0, [(1, 2)]), (1, [(1, 2), (3, 4)])], trailing=b'\0' * 6)
+    report = verify(DATA_PATH, 'set')
+    assert _problems(report) == [(40, None, None, 'truncated_record')]
+
+    # The last record claims more intervals than remain
+    _write_raw([(0, [(1, 2)])], trailing=struct.pack('<IIII', 1, 5, 1, 2))
+    report = verify(DATA_PATH, 'set')
+    assert _problems(report) == [(16, 1, None, 'truncated_record')]
+    assert report['id_count'] == 1
+
+
+def test_max_problems():
+    _write_raw([(0, [(i, i) for i in range(100)])])
+    report = verify(DATA_PATH, 'set', max_problems=10)
+    assert len(report['problems']) == 10
+    assert report['problems_truncated']
+
+
+def test_requires_kind():
+    _write_raw([(0, [(1, 2)])])
+    with pytest.raises(ValueError, match='kind is required'):
+        verify(DATA_PATH)
+    with pytest.raises(ValueError, match='payload_len is required'):
+        verify(DATA_PATH, 'list')
@@ -0,0 +1,204 @@
+import os
+import pytest
+import random
+from array import array
+
+from rs_intervalset import IntervalSetMappingWriter, \
+    IntervalListMappingWriter, MmapIntervalListMapping, MmapIntervalSetMapping
+from rs_intervalset import writer as py_writer
+
+
+CURRENT_DIR = os.path.abspath(os.path.dirname(__file__))
+PY_DATA_PATH = os.path.join(CURRENT_DIR, '.writer.test_py.bin')
+RS_DATA_PATH = os.path.join(CURRENT_DIR, '.writer.test_rs.bin')
+
+PAYLOAD_LEN = 3
+N = 100
+M = 100
+MAX_T = 100000
+OPTIONS = [{}, {'index': True}, {'header': True},
+           {'index': True, 'header': True}]
+
+
+@pytest.fixture(scope='session', autouse=True)
+def cleanup():
+    yield
+    for path in (PY_DATA_PATH, RS_DATA_PATH):
+        if os.path.exists(path):
+            os.remove(path)
+
+
+def _random_intervals(payload):
+    if not payload:
+        # Sets must be sorted and non-overlapping
+        points = sorted(random.sample(range(MAX_T), 2 * random.randint(1, M)))
+        return list(zip(points[::2], points[1::2]))
+    intervals = []
+    for _ in range(random.randint(1, M)):
+        a = random.randint(0, MAX_T - 1)
+        b = random.randint(a + 1, MAX_T)
+        intervals.append((a, b, random.randint(0, 2 ** 24 - 1)))
+    intervals.sort()
+    return intervals
+
+
+def _read(path):
+    with open(path, 'rb') as f:
+        return f.read()
+
+
+def _as_buffer(intervals, width):
+    """Get a 2D buffer without depending on numpy"""
+    flat = array('Q', [x for row in intervals for x in row])
+    return memoryview(flat).cast('B').cast('Q', shape=[len(intervals), width])
+
+
+def test_set_writer_identical():
+    data = {i: _random_intervals(False) for i in range(N)}
+    for options in OPTIONS:
+        with py_writer.IntervalSetMappingWriter(PY_DATA_PATH, **options) as w:
+            for i in data:
+                w.write(i, data[i])
+        with IntervalSetMappingWriter(RS_DATA_PATH, **options) as w:
+            for i in data:
+                w.write(i, data[i])
+        assert _read(PY_DATA_PATH) == _read(RS_DATA_PATH), options
+
+        # Append the ids again
+        with py_writer.IntervalSetMappingWriter(
+            PY_DATA_PATH, append=True, **options
+        ) as w:
+            for i in data:
+                w.write(i, data[i])
+        with IntervalSetMappingWriter(RS_DATA_PATH, append=True,
+                                      **options) as w:
+            for i in data:
+                w.write(i, _as_buffer(data[i], 2))
+        assert _read(PY_DATA_PATH) == _read(RS_DATA_PATH), options
+
+
+def test_list_writer_identical():
+    data = {i: _random_intervals(True) for i in range(N)}
+    for options in OPTIONS:
+        with py_writer.IntervalListMappingWriter(
+            PY_DATA_PATH, PAYLOAD_LEN, **options
+        ) as w:
+            for i in data:
+                w.write(i, data[i])
+        with IntervalListMappingWriter(
+            RS_DATA_PATH, PAYLOAD_LEN, **options
+        ) as w:
+            for i in data:
+                w.write(i, _as_buffer(data[i], 3))
+        assert _read(PY_DATA_PATH) == _read(RS_DATA_PATH), options
+
+
+def test_numpy_input():
+    np = pytest.importorskip('numpy')
+    data = {i: _random_intervals(False) for i in range(N)}
+    with py_writer.IntervalSetMappingWriter(PY_DATA_PATH) as w:
+        for i in data:
+            w.write(i, data[i])
+    with IntervalSetMappingWriter(RS_DATA_PATH) as w:
+        for i in data:
+            w.write(i, np.array(data[i], dtype=np.int64).reshape(-1, 2))
+    assert _read(PY_DATA_PATH) == _read(RS_DATA_PATH)
+
+
+def test_invalid_interval():
+    with IntervalSetMappingWriter(RS_DATA_PATH) as w:
+        with pytest.raises(AssertionError, match='invalid interval'):
+            w.write(0, [(10, 10)])
+    with IntervalListMappingWriter(RS_DATA_PATH, PAYLOAD_LEN) as w:
+        with pytest.raises(AssertionError, match='invalid interval'):
+            w.write(0, _as_buffer([(10, 5, 0)], 3))
+
+
+def test_set_validation():
+    for cls in (py_writer.IntervalSetMappingWriter, IntervalSetMappingWriter):
+        with cls(RS_DATA_PATH) as w:
+            with pytest.raises(AssertionError, match='interval 1: .* sorted'):
+                w.write(0, [(10, 20), (0, 5)])
+            with pytest.raises(AssertionError, match='interval 2: .* overlaps'):
+                w.write(0, [(0, 5), (10, 20), (15, 30)])
+
+
+def test_set_repair():
+    intervals = [(10, 20), (0, 5), (15, 30), (30, 40), (1, 2)]
+    for cls in (py_writer.IntervalSetMappingWriter, IntervalSetMappingWriter):
+        with cls(RS_DATA_PATH, repair=True) as w:
+            w.write(0, intervals)
+        isetmap = MmapIntervalSetMapping(RS_DATA_PATH, strict=True)
+        assert [(0, 5), (10, 30), (30, 40)] == isetmap.get_intervals(0, False)
+
+
+def test_index_on_append():
+    # Adding an index when appending to a file without one keeps the old ids
+    set_data = {i: _random_intervals(False) for i in range(3)}
+    list_data = {i: _random_intervals(True) for i in range(3)}
+    for cls in (py_writer.IntervalSetMappingWriter, IntervalSetMappingWriter):
+        for header in (False, True):
+            with cls(RS_DATA_PATH, header=header) as w:
+                w.write(1, set_data[1])
+                w.write(2, set_data[2])
+            with cls(RS_DATA_PATH, append=True, index=True,
+                     header=header) as w:
+                w.write(0, set_data[0])
+            isetmap = MmapIntervalSetMapping(RS_DATA_PATH)
+            assert [0, 1, 2] == isetmap.get_ids()
+            for i in set_data:
+                assert set_data[i] == isetmap.get_intervals(i, False)
+    for cls in (py_writer.IntervalListMappingWriter,
+                IntervalListMappingWriter):
+        for header in (False, True):
+            with cls(RS_DATA_PATH, PAYLOAD_LEN, header=header) as w:
+                w.write(1, list_data[1])
+                w.write(2, list_data[2])
+            with cls(RS_DATA_PATH, PAYLOAD_LEN, append=True, index=True,
+                     header=header) as w:
+                w.write(0, list_data[0])
+            ilistmap = MmapIntervalListMapping(RS_DATA_PATH, PAYLOAD_LEN)
+            assert [0, 1, 2] == ilistmap.get_ids()
+            for i in list_data:
+                assert list_data[i] == \
+                    ilistmap.get_intervals_with_payload(i, False)
+
+    # A truncated record cannot be indexed
+    with py_writer.IntervalListMappingWriter(RS_DATA_PATH, PAYLOAD_LEN) as w:
+        w.write(1, list_data[1])
+    with open(RS_DATA_PATH, 'ab') as f:
+        f.write(b'\x00' * 4)
+    with pytest.raises(ValueError, match='truncated'):
+        py_writer.IntervalListMappingWriter(
+            RS_DATA_PATH, PAYLOAD_LEN, append=True, index=True)
+    with pytest.raises(ValueError, match='truncated'):
+        IntervalListMappingWriter(
+            RS_DATA_PATH, PAYLOAD_LEN, append=True, index=True)
+
+
+def test_append_errors():
+    # Files that cannot be appended to raise ValueError and are left as is
+    data = _random_intervals(True)
+    for cls in (py_writer.IntervalListMappingWriter,
+                IntervalListMappingWriter):
+        with cls(RS_DATA_PATH, PAYLOAD_LEN, header=True) as w:
+            w.write(0, data)
+        with pytest.raises(ValueError, match='different header'):
+            cls(RS_DATA_PATH, PAYLOAD_LEN - 1, append=True)
+
+        with cls(RS_DATA_PATH, PAYLOAD_LEN) as w:
+            w.write(0, data)
+        with pytest.raises(ValueError, match='without one'):
+            cls(RS_DATA_PATH, PAYLOAD_LEN, append=True, header=True)
+
+        with cls(RS_DATA_PATH, PAYLOAD_LEN, index=True, header=True) as w:
+            w.write(0, data)
+        length = os.path.getsize(RS_DATA_PATH)
+        index_offset = length - 16 - 16
+        for bad_offset in (length, index_offset + 1, 8):
+            with open(RS_DATA_PATH, 'r+b') as f:
+                f.seek(length - 16)
+                f.write(bad_offset.to_bytes(8, byteorder='little'))
+            with pytest.raises(ValueError, match='invalid index'):
+                cls(RS_DATA_PATH, PAYLOAD_LEN, append=True)
+            assert length == os.path.getsize(RS_DATA_PATH)